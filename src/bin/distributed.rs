@@ -0,0 +1,87 @@
+#![allow(bare_trait_objects, clippy::redundant_field_names)]
+
+extern crate pbr;
+
+use pbr::scene::Scene;
+use pbr::sphere::Sphere;
+use pbr::material::Plastic;
+use pbr::surface::Surface;
+use pbr::renderer::Renderer;
+use pbr::sampler::{Sampler, SamplerConfiguration};
+use pbr::camera::Camera;
+use pbr::distributed::{Coordinator, Job, work};
+use std::env;
+use std::net::TcpListener;
+use std::process;
+
+// Renders the sphere of the simple example across machines:
+//
+//   distributed coordinator 0.0.0.0:7878 4
+//   distributed worker 192.168.1.10:7878
+const SCENE: &[u8] = b"simple";
+const WIDTH: usize = 40;
+const HEIGHT: usize = 20;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match (args.get(1).map(|a| a.as_str()), args.get(2)) {
+        (Some("coordinator"), Some(addr)) => {
+            let workers = args.get(3).and_then(|w| w.parse().ok()).unwrap_or(1);
+            coordinate(addr, workers)
+        }
+        (Some("worker"), Some(addr)) => {
+            let material = Plastic::new(1.0, 0.3, 0.4, 0.9);
+            let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(&material))];
+            let scene = Scene::new(&surfaces);
+            let camera = Camera::new(WIDTH, HEIGHT, 0.050, 0.024, 4.0);
+            let mut sampler = Sampler::new(&camera, &scene, SamplerConfiguration {
+                max_bounces: 10,
+                adapt: 4,
+            });
+
+            work(addr.as_str(), |job, tile| {
+                assert_eq!(SCENE, &job.scene[..]);
+                sampler.config = job.config.clone();
+                tile.render(&mut sampler, job.seed)
+            })
+        }
+        _ => {
+            eprintln!("usage: distributed coordinator <address> [workers] | distributed worker <address>");
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn coordinate(addr: &str, workers: usize) -> std::io::Result<()> {
+    let config = SamplerConfiguration {
+        max_bounces: 10,
+        adapt: 4,
+    };
+    let mut coordinator = Coordinator::new(Job {
+        scene: SCENE.to_vec(),
+        config: config.clone(),
+        width: WIDTH,
+        height: HEIGHT,
+        seed: 1,
+    });
+    coordinator.tile = 8;
+    coordinator.samples = 4;
+
+    let listener = TcpListener::bind(addr)?;
+    let samples = coordinator.run(&listener, workers)?;
+
+    // Only the camera of the sampler is needed to save the image
+    let surfaces: Vec<Box<Surface>> = Vec::new();
+    let scene = Scene::new(&surfaces);
+    let camera = Camera::new(WIDTH, HEIGHT, 0.050, 0.024, 4.0);
+    let mut sampler = Sampler::new(&camera, &scene, config);
+    sampler.samples = samples;
+    Renderer::new(&sampler).png();
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+#![allow(bare_trait_objects, dead_code, clippy::redundant_field_names)]
+
 use std::fmt::Debug;
 use std::f64::consts::PI;
 
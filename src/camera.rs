@@ -1,8 +1,10 @@
-use rand::{random, Rand, Rng, ThreadRng};
+use rand::{Rand, Rng, XorShiftRng};
 use cgmath::{Point3, Vector3, Matrix4, BaseNum, ApproxEq, BaseFloat};
-use cgmath::{ElementWise, InnerSpace};
+use cgmath::{InnerSpace, SquareMatrix, Transform};
 use num::traits::{zero, Zero, one, One, FloatConst, FromPrimitive};
-use ray::Ray;
+use ray3::Ray3;
+use direction::Direction;
+use vector3;
 
 #[derive(Debug)]
 pub struct Camera<T> {
@@ -24,7 +26,7 @@ impl<T: BaseNum + BaseFloat + ApproxEq + Zero + One + Rand + FloatConst + FromPr
     pub fn new(width: usize, height: usize, lens: T, sensor: T, f_stop: T) -> Camera<T> {
         let position = Point3::new(zero::<T>(), zero::<T>(), one::<T>());
         let target = Point3::new(zero::<T>(), zero::<T>(), zero::<T>());
-        let focus = target.clone();
+        let focus = target;
 
         Camera {
             width: width,
@@ -63,7 +65,12 @@ impl<T: BaseNum + BaseFloat + ApproxEq + Zero + One + Rand + FloatConst + FromPr
         Point3::new(-x, y, z)
     }
 
-    pub fn aperture_point(&self, rng: &mut ThreadRng) -> Point3<T> {
+    // Angle between the rays through neighbouring pixels at the center of the sensor
+    pub fn pixel_angle(&self) -> T {
+        self.sensor / (self.lens * T::from_usize(self.height).unwrap())
+    }
+
+    pub fn aperture_point(&self, rng: &mut XorShiftRng) -> Point3<T> {
         let d = self.lens / self.f_stop;
         let t = (one::<T>() + one::<T>()) * T::PI() * rng.gen::<T>();
         let r = (rng.gen::<T>() * d) / (one::<T>() + one::<T>());
@@ -72,3 +79,46 @@ impl<T: BaseNum + BaseFloat + ApproxEq + Zero + One + Rand + FloatConst + FromPr
         Point3::new(x, y, zero::<T>())
     }
 }
+
+impl Camera<f64> {
+    // Ray through the point `x`, `y` of the image, both in [0, 1), jittered
+    // within the pixel and focused through a random point of the aperture
+    pub fn ray(&self, x: f64, y: f64, rng: &mut XorShiftRng) -> Ray3 {
+        let px = x + rng.gen_range(0.0, 1.0) / self.width as f64;
+        let py = y + rng.gen_range(0.0, 1.0) / self.height as f64;
+        let sensor_pt = self.sensor_point(px, py);
+        let straight = -(sensor_pt - Point3::new(0.0, 0.0, 0.0)).normalize();
+        let focal_pt = Point3::new(0.0, 0.0, 0.0) + straight * self.focus;
+        let lens_pt = self.aperture_point(rng);
+        let refracted = (focal_pt - lens_pt).normalize();
+
+        // `pos` looks from the camera, rays go the other way
+        let world = self.pos.invert().unwrap();
+        let origin = world.transform_point(lens_pt);
+        let direction = world.transform_vector(refracted).normalize();
+        Ray3 {
+            origin: vector3::Vector3 { x: origin.x, y: origin.y, z: origin.z },
+            direction: Direction { x: direction.x, y: direction.y, z: direction.z },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Camera;
+    use rand::{SeedableRng, XorShiftRng};
+
+    #[test]
+    fn camera_ray() {
+        // From the lens at z = 1 towards the target at the origin
+        let camera = Camera::new(64, 32, 0.050, 0.024, 4.0);
+        let mut rng = XorShiftRng::from_seed([1, 1, 2, 3]);
+        let center = camera.ray(0.5, 0.5, &mut rng);
+        assert!((center.origin.z - 1.0).abs() < 0.01, "{:?}", center);
+        assert!(center.direction.z < -0.99, "{:?}", center);
+
+        // The top left of the image looks up and to the left
+        let corner = camera.ray(0.0, 0.0, &mut rng);
+        assert!(corner.direction.x < 0.0 && corner.direction.y > 0.0, "{:?}", corner);
+    }
+}
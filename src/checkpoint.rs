@@ -0,0 +1,97 @@
+use sampler::{Sampler, SamplerConfiguration};
+use sample::Sample;
+use encode::{read_samples, read_u64, write_samples, write_u64};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"PBRC";
+const VERSION: u64 = 1;
+
+// Everything needed to continue a progressive render where it left off
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub seed: u32, // Seed of the per pass random sequence
+    pub pass: usize, // Next pass to render
+    pub spp: usize, // Samples per pixel of the completed passes
+    pub scene: u64, // Hash of the scene the samples belong to
+    pub config: SamplerConfiguration,
+    pub width: usize,
+    pub height: usize,
+    pub samples: Vec<Vec<Sample>>,
+}
+
+impl Checkpoint {
+    pub fn new(sampler: &Sampler, seed: u32, pass: usize, spp: usize) -> Checkpoint {
+        Checkpoint {
+            seed: seed,
+            pass: pass,
+            spp: spp,
+            scene: sampler.scene.hash(),
+            config: sampler.config.clone(),
+            width: sampler.cam.width,
+            height: sampler.cam.height,
+            samples: sampler.samples.clone(),
+        }
+    }
+
+    // Writes to a temporary file first so a crash never leaves a truncated checkpoint behind
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(File::create(&tmp)?);
+            w.write_all(MAGIC)?;
+            write_u64(&mut w, VERSION)?;
+            write_u64(&mut w, self.seed as u64)?;
+            write_u64(&mut w, self.pass as u64)?;
+            write_u64(&mut w, self.spp as u64)?;
+            write_u64(&mut w, self.scene)?;
+            write_u64(&mut w, self.config.max_bounces as u64)?;
+            write_u64(&mut w, self.config.adapt as u64)?;
+            write_u64(&mut w, self.width as u64)?;
+            write_u64(&mut w, self.height as u64)?;
+            write_samples(&mut w, &self.samples)?;
+            w.flush()?;
+        }
+        fs::rename(&tmp, path)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Checkpoint> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+
+        if &magic != MAGIC || read_u64(&mut r)? != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "not a checkpoint file"));
+        }
+
+        Ok(Checkpoint {
+            seed: read_u64(&mut r)? as u32,
+            pass: read_u64(&mut r)? as usize,
+            spp: read_u64(&mut r)? as usize,
+            scene: read_u64(&mut r)?,
+            config: SamplerConfiguration {
+                max_bounces: read_u64(&mut r)? as usize,
+                adapt: read_u64(&mut r)? as usize,
+            },
+            width: read_u64(&mut r)? as usize,
+            height: read_u64(&mut r)? as usize,
+            samples: read_samples(&mut r)?,
+        })
+    }
+
+    // Loads the samples into the sampler, refusing if the scene or configuration changed
+    pub fn restore(self, sampler: &mut Sampler) -> io::Result<(u32, usize, usize)> {
+        if self.scene != sampler.scene.hash() {
+            return Err(Error::new(ErrorKind::InvalidData, "scene changed since the checkpoint was written"));
+        }
+
+        if self.config != sampler.config || self.width != sampler.cam.width || self.height != sampler.cam.height {
+            return Err(Error::new(ErrorKind::InvalidData, "sampler configuration changed since the checkpoint was written"));
+        }
+
+        sampler.samples = self.samples;
+
+        Ok((self.seed, self.pass, self.spp))
+    }
+}
@@ -0,0 +1,263 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, absorb, pass, black};
+use microfacet::{Ggx, reflect, fresnel_conductor};
+use surface::Geometry;
+use texture::Texture;
+use thinfilm::{ThinFilm, BANDS};
+use rand::{Rng, XorShiftRng};
+
+// Wavelength in nm, eta and k of a measured conductor
+pub type Spectrum = &'static [(f64, f64, f64)];
+
+// Approximate measured data, Johnson and Christy (1972) for the noble metals and
+// Rakić (1995) for aluminium, sampled every 50 nm.
+pub const GOLD: Spectrum = &[(400.0, 1.66, 1.96), (450.0, 1.40, 1.88), (500.0, 0.92, 1.84), (550.0, 0.43, 2.45),
+                             (600.0, 0.25, 2.98), (650.0, 0.15, 3.55), (700.0, 0.13, 4.05)];
+pub const SILVER: Spectrum = &[(400.0, 0.05, 2.07), (450.0, 0.04, 2.65), (500.0, 0.05, 3.09), (550.0, 0.06, 3.59),
+                               (600.0, 0.06, 4.00), (650.0, 0.05, 4.48), (700.0, 0.04, 4.80)];
+pub const COPPER: Spectrum = &[(400.0, 1.18, 2.21), (450.0, 1.14, 2.50), (500.0, 1.12, 2.60), (550.0, 1.00, 2.58),
+                               (600.0, 0.36, 3.20), (650.0, 0.21, 3.95), (700.0, 0.21, 4.20)];
+pub const ALUMINIUM: Spectrum = &[(400.0, 0.49, 4.86), (450.0, 0.62, 5.47), (500.0, 0.77, 6.08), (550.0, 0.96, 6.69),
+                                  (600.0, 1.20, 7.26), (650.0, 1.49, 7.82), (700.0, 1.83, 8.31)];
+
+// Wavelengths the red, green and blue channels stand for
+const RGB_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+// Rough metal with the full Fresnel equations of a complex index of refraction,
+// unlike `Metal` which only knows the reflectance at normal incidence
+#[derive(Debug)]
+pub struct Conductor {
+    pub eta: Energy,
+    pub k: Energy,
+    pub roughness: Texture,
+    pub anisotropic: Texture, // Rougher along the tangent, for brushed metal
+    pub tangent: Option<Texture>, // Tangent map, see `Geometry::frame`
+    pub spectrum: Option<Spectrum>, // Measured data, averaged over the band of each channel
+    pub film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Energy, k: Energy, roughness: f64) -> Conductor {
+        Conductor {
+            eta: eta,
+            k: k,
+            roughness: Texture::value(roughness),
+            anisotropic: Texture::value(0.0),
+            tangent: None,
+            spectrum: None,
+            film: None,
+        }
+    }
+
+    pub fn measured(spectrum: Spectrum, roughness: f64) -> Conductor {
+        let at = |i: usize| index(spectrum, RGB_WAVELENGTHS[i]);
+        let (r, g, b) = (at(0), at(1), at(2));
+
+        Conductor {
+            eta: Energy { x: r.0, y: g.0, z: b.0 },
+            k: Energy { x: r.1, y: g.1, z: b.1 },
+            roughness: Texture::value(roughness),
+            anisotropic: Texture::value(0.0),
+            tangent: None,
+            spectrum: Some(spectrum),
+            film: None,
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::measured(GOLD, roughness)
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::measured(SILVER, roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::measured(COPPER, roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::measured(ALUMINIUM, roughness)
+    }
+
+    pub fn fresnel(&self, cos: f64) -> Energy {
+        // Measured metals average their spectrum over the band of each channel
+        if self.spectrum.is_some() {
+            let band = |i: usize| BANDS[i].iter().map(|&w| self.spectral(cos, w)).sum::<f64>() / BANDS[i].len() as f64;
+            return Energy { x: band(0), y: band(1), z: band(2) };
+        }
+
+        if let Some(ref film) = self.film {
+            return film.rgb(cos, &self.eta, &self.k);
+        }
+
+        Energy {
+            x: fresnel_conductor(cos, self.eta.x, self.k.x),
+            y: fresnel_conductor(cos, self.eta.y, self.k.y),
+            z: fresnel_conductor(cos, self.eta.z, self.k.z),
+        }
+    }
+
+    // Reflectance at a single wavelength in nm. Without measured data the channel
+    // closest to the wavelength is used.
+    pub fn spectral(&self, cos: f64, wavelength: f64) -> f64 {
+        let (eta, k) = match self.spectrum {
+            Some(spectrum) => index(spectrum, wavelength),
+            None if wavelength >= 580.0 => (self.eta.x, self.k.x),
+            None if wavelength >= 500.0 => (self.eta.y, self.k.y),
+            None => (self.eta.z, self.k.z),
+        };
+
+        match self.film {
+            Some(ref film) => film.reflectance(cos, wavelength, eta, k),
+            None => fresnel_conductor(cos, eta, k),
+        }
+    }
+
+    // Brushed along the tangent
+    pub fn brushed(mut self, anisotropic: f64) -> Conductor {
+        self.anisotropic = Texture::value(anisotropic);
+        self
+    }
+
+    fn ggx(&self, g: &Geometry) -> Ggx {
+        Ggx::roughness(self.roughness.filtered_scalar(g), self.anisotropic.filtered_scalar(g))
+    }
+}
+
+impl Material for Conductor {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        if !inc.enters(&g.normal) {
+            return pass(inc);
+        }
+
+        let frame = g.frame(self.tangent.as_ref());
+        let wo = frame.local(&inc.invert());
+        let ggx = self.ggx(g);
+        let m = ggx.sample(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+        let wi = reflect(&wo, &m);
+        let cos = wo.dot(&m);
+        if wi.z <= 0.0 || cos <= 0.0 {
+            return absorb(inc);
+        }
+
+        let strength = self.fresnel(cos).amplified(ggx.g(&wo, &wi) * cos / (wo.z * m.z));
+        (true, frame.world(&wi), strength, false)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        let frame = g.frame(self.tangent.as_ref());
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return black();
+        }
+
+        let ggx = self.ggx(g);
+        let h = (&wo + &wi).unit();
+        self.fresnel(wo.dot(&h)).amplified(ggx.d(&h) * ggx.g(&wo, &wi) / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        let frame = g.frame(self.tangent.as_ref());
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let h = (&wo + &wi).unit();
+        self.ggx(g).pdf(&h) / (4.0 * wo.dot(&h))
+    }
+
+    fn albedo(&self, _: &Geometry) -> Energy {
+        self.fresnel(1.0)
+    }
+
+    fn digest(&self) -> u64 {
+        let spectrum = self.spectrum.unwrap_or(&[]).iter().fold(Digest::new("Spectrum"), |d, &(w, eta, k)| d.values(&[w, eta, k]));
+        Digest::new("Conductor")
+            .vector(&self.eta)
+            .vector(&self.k)
+            .with(self.roughness.digest())
+            .with(self.anisotropic.digest())
+            .with(self.tangent.as_ref().map_or(0, |t| t.digest()))
+            .with(spectrum.finish())
+            .with(self.film.as_ref().map_or(0, |f| f.digest()))
+            .finish()
+    }
+}
+
+// Linear interpolation of eta and k, clamped to the measured range
+fn index(spectrum: Spectrum, wavelength: f64) -> (f64, f64) {
+    let first = spectrum[0];
+    let last = spectrum[spectrum.len() - 1];
+    if wavelength <= first.0 {
+        return (first.1, first.2);
+    }
+    if wavelength >= last.0 {
+        return (last.1, last.2);
+    }
+
+    let i = spectrum.iter().position(|s| s.0 > wavelength).unwrap();
+    let (a, b) = (spectrum[i - 1], spectrum[i]);
+    let t = (wavelength - a.0) / (b.0 - a.0);
+    (a.1 + (b.1 - a.1) * t, a.2 + (b.2 - a.2) * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Conductor;
+    use material::Material;
+    use texture::Texture;
+    use surface::Geometry;
+    use direction::Direction;
+    use furnace;
+    use rand::{SeedableRng, XorShiftRng};
+
+    #[test]
+    fn conductor_presets() {
+        let gold = Conductor::gold(0.0).fresnel(1.0);
+        assert!(gold.x > 0.9 && gold.z < 0.5);
+
+        let copper = Conductor::copper(0.0).fresnel(1.0);
+        assert!(copper.x > copper.y && copper.y > copper.z);
+
+        // Every metal turns into a mirror at grazing angles
+        let grazing = Conductor::aluminium(0.0).fresnel(0.001);
+        assert!(grazing.average() > 0.95);
+
+        // Green averages the measured reflectance from 500 to 600 nm
+        let silver = Conductor::silver(0.0);
+        let green = [500.0, 525.0, 550.0, 575.0, 600.0].iter().map(|&w| silver.spectral(1.0, w)).sum::<f64>() / 5.0;
+        assert!((green - silver.fresnel(1.0).y).abs() < 1e-9);
+    }
+
+    #[test]
+    fn conductor_conserves_energy() {
+        let g = Geometry::new(&Direction { x: 0.0, y: 1.0, z: 0.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.6, y: -0.8, z: 0.0 };
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let m = Conductor::silver(0.5);
+
+        let albedo = furnace::albedo(&m, &g, &inc, 100000, &mut rng).average();
+        assert!(albedo < 1.0 && albedo > 0.8, "albedo {}", albedo);
+    }
+
+    #[test]
+    fn conductor_brushed() {
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.0, y: 0.0, z: -1.0 };
+        let tilt = |d: &Direction| (&Direction { x: 0.0, y: 0.0, z: 1.0 } + &d.amplified(0.3)).unit();
+        let (along, across) = (tilt(&g.tangent), tilt(&g.bitangent));
+
+        // The highlight stretches along the tangent
+        let brushed = Conductor::aluminium(0.3).brushed(0.9);
+        assert!(brushed.evaluate(&g, &inc, &along).x > 2.0 * brushed.evaluate(&g, &inc, &across).x);
+
+        // A tangent map turns the brushing direction
+        let mut turned = Conductor::aluminium(0.3).brushed(0.9);
+        turned.tangent = Some(Texture::constant(0.5, 1.0, 0.0));
+        assert!(turned.evaluate(&g, &inc, &across).x > 2.0 * turned.evaluate(&g, &inc, &along).x);
+    }
+}
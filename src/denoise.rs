@@ -0,0 +1,204 @@
+use sample::{Sample, luminance};
+use energy::Energy;
+use direction::Direction;
+
+// B3 spline used by the à-trous wavelet transform
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Edge-avoiding à-trous wavelet filter guided by the albedo and normal feature
+// buffers and the per pixel variance of the radiance.
+#[derive(Debug)]
+pub struct Denoiser {
+    pub iterations: usize, // Number of wavelet passes, the filter footprint doubles every pass
+    pub sigma_color: f64, // Luminance tolerance in standard deviations
+    pub sigma_albedo: f64, // Albedo tolerance
+    pub sigma_normal: f64, // Exponent applied to the cosine between normals
+}
+
+struct Pixel {
+    color: Energy,
+    variance: f64,
+    albedo: Energy,
+    normal: Direction,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser::new()
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_albedo: 0.1,
+            sigma_normal: 128.0,
+        }
+    }
+
+    // Filters the samples of any sampler, the input is left untouched so the
+    // noisy original can be kept alongside the result
+    pub fn denoise(&self, samples: &[Vec<Sample>]) -> Vec<Vec<Sample>> {
+        let width = samples.len();
+        let height = if width > 0 { samples[0].len() } else { 0 };
+
+        let mut pixels: Vec<Vec<Pixel>> = samples.iter().map(|column| column.iter().map(pixel).collect()).collect();
+
+        for i in 0..self.iterations {
+            let step = 1 << i;
+            let mut filtered = Vec::with_capacity(width);
+
+            for x in 0..width {
+                let mut column = Vec::with_capacity(height);
+                for y in 0..height {
+                    column.push(self.filter(&pixels, x, y, step));
+                }
+                filtered.push(column);
+            }
+
+            pixels = filtered;
+        }
+
+        samples.iter()
+            .zip(pixels.iter())
+            .map(|(column, filtered)| {
+                column.iter()
+                    .zip(filtered.iter())
+                    .map(|(s, p)| {
+                        let count = s.count as f64;
+                        Sample {
+                            red: p.color.x * count,
+                            green: p.color.y * count,
+                            blue: p.color.z * count,
+                            count: s.count,
+                            square: (p.variance * (count - 1.0).max(0.0) / count.max(1.0) + luminance(&p.color).powi(2)) * count,
+                            albedo: s.albedo.clone(),
+                            normal: s.normal.clone(),
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn filter(&self, pixels: &[Vec<Pixel>], x: usize, y: usize, step: usize) -> Pixel {
+        let (width, height) = (pixels.len() as isize, pixels[0].len() as isize);
+        let p = &pixels[x][y];
+        let l = luminance(&p.color);
+        let deviation = self.sigma_color * p.variance.sqrt() + 1e-6;
+
+        let mut color = Energy { x: 0.0, y: 0.0, z: 0.0 };
+        let mut variance = 0.0;
+        let mut total = 0.0;
+
+        for (i, hx) in KERNEL.iter().enumerate() {
+            for (j, hy) in KERNEL.iter().enumerate() {
+                let qx = x as isize + (i as isize - 2) * step as isize;
+                let qy = y as isize + (j as isize - 2) * step as isize;
+
+                if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                    continue;
+                }
+
+                let q = &pixels[qx as usize][qy as usize];
+                let wl = (-(l - luminance(&q.color)).abs() / deviation).exp();
+                let da = &p.albedo - &q.albedo;
+                let wa = (-da.dot(&da) / (self.sigma_albedo * self.sigma_albedo)).exp();
+                let wn = if p.normal.len() == 0.0 && q.normal.len() == 0.0 {
+                    1.0
+                } else {
+                    p.normal.dot(&q.normal).max(0.0).powf(self.sigma_normal)
+                };
+                let w = hx * hy * wl * wa * wn;
+
+                color = &color + &(&q.color * w);
+                variance += w * w * q.variance;
+                total += w;
+            }
+        }
+
+        // The center pixel always contributes, so total is never zero
+        Pixel {
+            color: &color * (1.0 / total),
+            variance: variance / (total * total),
+            albedo: p.albedo.clone(),
+            normal: p.normal.clone(),
+        }
+    }
+}
+
+fn pixel(s: &Sample) -> Pixel {
+    let count = (s.count as f64).max(1.0);
+    let normal = if s.normal.len() > 0.0 {
+        s.normal.unit()
+    } else {
+        s.normal.clone()
+    };
+
+    Pixel {
+        color: s.mean(),
+        variance: s.variance() / count,
+        albedo: &s.albedo * (1.0 / count),
+        normal: normal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Denoiser;
+    use sample::Sample;
+    use energy::Energy;
+    use direction::Direction;
+
+    fn noisy(width: usize, height: usize) -> Vec<Vec<Sample>> {
+        let albedo = Energy { x: 0.5, y: 0.5, z: 0.5 };
+        let normal = Direction { x: 0.0, y: 0.0, z: 1.0 };
+        let mut samples = vec![vec![Sample::new(); height]; width];
+
+        for (x, column) in samples.iter_mut().enumerate() {
+            for (y, sample) in column.iter_mut().enumerate() {
+                for i in 0..4 {
+                    let v = if (x + y + i) % 2 == 0 { 0.0 } else { 200.0 + (x * 7 + y * 13) as f64 % 50.0 };
+                    sample.add(&Energy { x: v, y: v, z: v }, &albedo, &normal);
+                }
+            }
+        }
+
+        samples
+    }
+
+    #[test]
+    fn denoise_reduces_noise() {
+        let samples = noisy(16, 16);
+        let denoised = Denoiser::new().denoise(&samples);
+
+        let spread = |s: &[Vec<Sample>]| {
+            let values: Vec<f64> = s.iter().flat_map(|c| c.iter().map(|p| p.mean().x)).collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+        };
+
+        assert!(spread(&denoised) < spread(&samples) * 0.5);
+        assert_eq!(samples[3][4].count, denoised[3][4].count);
+    }
+
+    #[test]
+    fn denoise_keeps_edges() {
+        let mut samples = vec![vec![Sample::new(); 8]; 8];
+        let normal = Direction { x: 0.0, y: 0.0, z: 1.0 };
+
+        for (x, column) in samples.iter_mut().enumerate() {
+            let (v, albedo) = if x < 4 { (0.0, 0.0) } else { (255.0, 1.0) };
+            for sample in column.iter_mut() {
+                sample.add(&Energy { x: v, y: v, z: v }, &Energy { x: albedo, y: albedo, z: albedo }, &normal);
+            }
+        }
+
+        let denoised = Denoiser::new().denoise(&samples);
+
+        assert_eq!(0.0, denoised[3][0].mean().x);
+        assert_eq!(255.0, denoised[4][0].mean().x);
+    }
+}
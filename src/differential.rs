@@ -0,0 +1,128 @@
+use vector3::Vector3;
+use direction::Direction;
+use microfacet::Frame;
+use surface::Geometry;
+
+// Change of a ray's origin and direction from one pixel to the next along x and y
+// of the image, used to size texture lookups to the area a pixel covers.
+#[derive(Debug, Clone)]
+pub struct Differential {
+    pub dpdx: Vector3,
+    pub dpdy: Vector3,
+    pub dddx: Direction,
+    pub dddy: Direction,
+}
+
+impl Differential {
+    // Rays from a pinhole spreading by `angle` per pixel, the lens is ignored
+    pub fn camera(direction: &Direction, angle: f64) -> Differential {
+        let frame = Frame::new(direction);
+        let zero = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+
+        Differential {
+            dpdx: zero.clone(),
+            dpdy: zero,
+            dddx: &frame.tangent * angle,
+            dddy: &frame.bitangent * angle,
+        }
+    }
+
+    // Follows the ray `dist` along `direction` to the tangent plane of `normal`
+    pub fn transfer(&self, direction: &Direction, dist: f64, normal: &Direction) -> Differential {
+        let cos = direction.dot(normal);
+        let offset = |dp: &Vector3, dd: &Direction| {
+            let moved = dp + &(dd * dist);
+            if cos == 0.0 {
+                return moved;
+            }
+            let dt = -moved.dot(normal) / cos;
+            &moved + &(direction * dt)
+        };
+
+        Differential {
+            dpdx: offset(&self.dpdx, &self.dddx),
+            dpdy: offset(&self.dpdy, &self.dddy),
+            dddx: self.dddx.clone(),
+            dddy: self.dddy.clone(),
+        }
+    }
+
+    // Width of the footprint in texture coordinates
+    pub fn footprint(&self, g: &Geometry) -> f64 {
+        let (lu, lv) = (g.dpdu.dot(&g.dpdu), g.dpdv.dot(&g.dpdv));
+        if lu == 0.0 || lv == 0.0 {
+            return 0.0;
+        }
+
+        let du = (self.dpdx.dot(&g.dpdu) / lu).abs().max((self.dpdy.dot(&g.dpdu) / lu).abs());
+        let dv = (self.dpdx.dot(&g.dpdv) / lv).abs().max((self.dpdy.dot(&g.dpdv) / lv).abs());
+        du.max(dv)
+    }
+
+    // Follows a bounce from `inc` to `out`. Only mirror reflections and sharp refractions
+    // keep a meaningful footprint, any other bounce returns None.
+    pub fn scattered(&self, inc: &Direction, out: &Direction, normal: &Direction) -> Option<Differential> {
+        let normal = if inc.enters(normal) { normal.clone() } else { normal.invert() };
+
+        if out.dot(&inc.reflected(&normal)) > 1.0 - 1e-9 {
+            return Some(Differential {
+                dpdx: self.dpdx.clone(),
+                dpdy: self.dpdy.clone(),
+                dddx: inc.reflected_differential(&normal, &self.dddx),
+                dddy: inc.reflected_differential(&normal, &self.dddy),
+            });
+        }
+
+        // The ratio of the indices follows from the angles on both sides
+        let cos_i = normal.cos(inc);
+        let cos_t = normal.cos(out);
+        let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+        let sin_t = (1.0 - cos_t * cos_t).max(0.0).sqrt();
+        if !out.enters(&normal) || sin_i < 1e-6 {
+            return None;
+        }
+
+        let ratio = sin_t / sin_i;
+        let (refracted, straight) = inc.refracted(&normal, ratio, 1.0);
+        if !refracted || out.dot(&straight) < 1.0 - 1e-9 {
+            return None;
+        }
+
+        Some(Differential {
+            dpdx: self.dpdx.clone(),
+            dpdy: self.dpdy.clone(),
+            dddx: inc.refracted_differential(&normal, ratio, 1.0, &self.dddx),
+            dddy: inc.refracted_differential(&normal, ratio, 1.0, &self.dddy),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Differential;
+    use direction::Direction;
+    use surface::Geometry;
+
+    #[test]
+    fn differential_footprint() {
+        // Looking straight at a plane with unit texture scale, the footprint grows with distance
+        let normal = Direction { x: 0.0, y: 0.0, z: 1.0 };
+        let down = Direction { x: 0.0, y: 0.0, z: -1.0 };
+        let g = Geometry::new(&normal, 0.5, 0.5);
+
+        let near = Differential::camera(&down, 0.001).transfer(&down, 1.0, &normal);
+        let far = Differential::camera(&down, 0.001).transfer(&down, 10.0, &normal);
+        assert!((near.footprint(&g) - 0.001).abs() < 1e-9);
+        assert!((far.footprint(&g) - 0.01).abs() < 1e-9);
+
+        // A mirror keeps the spread, a diffuse bounce loses it
+        let inc = Direction { x: 0.6, y: 0.0, z: -0.8 };
+        let d = Differential::camera(&inc, 0.001);
+        assert!(d.scattered(&inc, &inc.reflected(&normal), &normal).is_some());
+        assert!(d.scattered(&inc, &normal, &normal).is_none());
+
+        let (_, refr) = inc.refracted(&normal, 1.0, 1.5);
+        let r = d.scattered(&inc, &refr, &normal).unwrap();
+        assert!(r.dddx.len() > 0.0 && r.dddx.len() < 0.001 + 1e-9);
+    }
+}
@@ -1,6 +1,6 @@
 use vector3::Vector3;
 use std::f64::consts::PI;
-use rand::{Rng, ThreadRng};
+use rand::{Rng, XorShiftRng};
 
 pub type Direction = Vector3;
 
@@ -14,7 +14,7 @@ impl Direction {
     }
 
     pub fn enters(&self, normal: &Direction) -> bool {
-        normal.dot(&self) < 0.0
+        normal.dot(self) < 0.0
     }
 
     pub fn cos(&self, b: &Direction) -> f64 {
@@ -39,10 +39,25 @@ impl Direction {
         (self - &(&(normal * 2.0) * cos)).unit()
     }
 
-    pub fn cone(&self, size: f64, rng: &mut ThreadRng) -> Direction {
-        let u = rng.gen_range(0.0, 1.0);
+    // Change of `reflected` for a change `d` of this direction, the normal held fixed
+    pub fn reflected_differential(&self, normal: &Direction, d: &Direction) -> Direction {
+        d - &(normal * (2.0 * normal.cos(d)))
+    }
+
+    // Change of `refracted` for a change `d` of this direction, the normal held fixed
+    pub fn refracted_differential(&self, normal: &Direction, index_a: f64, index_b: f64, d: &Direction) -> Direction {
+        let ratio = index_a / index_b;
+        let cos = normal.cos(self);
+        let k = (1.0 - ratio * ratio * (1.0 - cos * cos)).max(1e-9);
+        let dmu = (ratio + ratio * ratio * cos / k.sqrt()) * normal.cos(d);
+
+        &(d * ratio) - &(normal * dmu)
+    }
+
+    pub fn cone(&self, size: f64, rng: &mut XorShiftRng) -> Direction {
+        let u: f64 = rng.gen_range(0.0, 1.0);
         let v = rng.gen_range(0.0, 1.0);
-        let theta = size * 0.5 * PI * (1.0 - (2.0 * (u as f64).acos() / PI));
+        let theta = size * 0.5 * PI * (1.0 - (2.0 * u.acos() / PI));
         let m1 = theta.sin();
         let m2 = theta.cos();
         let a2 = v * 2.0 * PI;
@@ -56,7 +71,7 @@ impl Direction {
         d.unit()
     }
 
-    pub fn random(rng: &mut ThreadRng) -> Direction {
+    pub fn random(rng: &mut XorShiftRng) -> Direction {
         Direction::angle_direction(rng.gen_range::<f64>(0.0, 2.0 * PI), rng.gen_range::<f64>(-1.0, 1.0).asin())
     }
 
@@ -68,11 +83,11 @@ impl Direction {
         }
     }
 
-    pub fn random_hemi_cos(&self, rng: &mut ThreadRng) -> Direction {
+    pub fn random_hemi_cos(&self, rng: &mut XorShiftRng) -> Direction {
         let u = rng.gen_range::<f64>(0.0, 1.0);
         let r = u.sqrt();
         let theta = rng.gen_range::<f64>(0.0, 2.0 * PI);
-        let s = self.cross(&Direction::random(rng));
+        let s = self.cross(&Direction::random(rng)).unit();
         let t = self.cross(&s);
         let mut d = Vector3{x: 0.0, y: 0.0, z: 0.0 };
         d = &d + &(&s * (r * theta.cos()));
@@ -85,6 +100,7 @@ impl Direction {
 #[cfg(test)]
 mod tests {
     use super::Direction;
+    use rand::{SeedableRng, XorShiftRng};
 
     #[test]
     fn direction_unit() {
@@ -101,4 +117,19 @@ mod tests {
                    },
                    d.unit());
     }
+
+    #[test]
+    fn cosine_weighted() {
+        // Unit directions with an average cosine of 2/3
+        let normal = Direction { x: 0.0, y: 0.6, z: 0.8 };
+        let mut rng = XorShiftRng::from_seed([2, 7, 1, 8]);
+        let n = 100000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let d = normal.random_hemi_cos(&mut rng);
+            assert!((d.len() - 1.0).abs() < 1e-9);
+            total += normal.dot(&d);
+        }
+        assert!((total / n as f64 - 2.0 / 3.0).abs() < 0.01);
+    }
 }
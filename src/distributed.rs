@@ -0,0 +1,456 @@
+use sampler::{Sampler, SamplerConfiguration};
+use sample::Sample;
+use encode::{read_bytes, read_samples, read_u64, write_bytes, write_samples, write_u64};
+use rand::{SeedableRng, XorShiftRng};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TILE: u64 = 1;
+const DONE: u64 = 0;
+
+// Sent to every worker once it connects. The scene description is opaque to the
+// renderer, workers build their scene from it however they see fit.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub scene: Vec<u8>,
+    pub config: SamplerConfiguration,
+    pub width: usize,
+    pub height: usize,
+    pub seed: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub pass: usize,
+}
+
+impl Tile {
+    // Samples the tile into a fresh buffer. The sequence is seeded by the tile
+    // so the result does not depend on which worker rendered it.
+    pub fn render(&self, sampler: &mut Sampler, seed: u32) -> io::Result<Vec<Vec<Sample>>> {
+        let mut rng = XorShiftRng::from_seed([seed, self.pass as u32, self.x as u32 + 1, self.y as u32 + 1]);
+        let mut samples = Vec::with_capacity(self.width);
+
+        for x in self.x..self.x + self.width {
+            let mut column = Vec::with_capacity(self.height);
+            for y in self.y..self.y + self.height {
+                sampler.samples[x][y] = Sample::new();
+                sampler.sample_pixel(x, y, &mut rng, self.samples);
+                column.push(sampler.samples[x][y].clone());
+            }
+            samples.push(column);
+        }
+        sampler.publish()?;
+
+        Ok(samples)
+    }
+}
+
+// Hands out tiles to connected workers and merges the returned samples
+#[derive(Debug)]
+pub struct Coordinator {
+    pub job: Job,
+    pub tile: usize, // Tile size in pixels
+    pub samples: usize, // Samples per pixel of every tile
+    pub passes: usize,
+    pub timeout: Duration, // Longest wait for a worker to connect or to return a tile
+}
+
+impl Coordinator {
+    pub fn new(job: Job) -> Coordinator {
+        Coordinator {
+            job: job,
+            tile: 32,
+            samples: 16,
+            passes: 1,
+            timeout: Duration::from_secs(600),
+        }
+    }
+
+    pub fn tiles(&self) -> Vec<Tile> {
+        assert!(self.tile > 0, "tile size must be positive");
+        let mut tiles = Vec::new();
+
+        for pass in 0..self.passes {
+            for x in (0..self.job.width).step_by(self.tile) {
+                for y in (0..self.job.height).step_by(self.tile) {
+                    tiles.push(Tile {
+                        x: x,
+                        y: y,
+                        width: self.tile.min(self.job.width - x),
+                        height: self.tile.min(self.job.height - y),
+                        samples: self.samples,
+                        pass: pass,
+                    });
+                }
+            }
+        }
+
+        tiles
+    }
+
+    // Waits for the given number of workers and renders every tile with them.
+    // Tiles of a worker that disconnects or doesn't answer within the timeout
+    // are handed to the remaining workers. Fails with the error of the last
+    // worker if none are left before the render completes.
+    pub fn run(&self, listener: &TcpListener, workers: usize) -> io::Result<Vec<Vec<Sample>>> {
+        if self.tile == 0 || workers == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "tile size and worker count must be positive"));
+        }
+
+        let queue = Arc::new(Mutex::new(self.tiles()));
+        let remaining = Arc::new(AtomicUsize::new(queue.lock().unwrap().len()));
+        let (tx, rx) = channel();
+        let mut threads = Vec::with_capacity(workers);
+
+        for _ in 0..workers {
+            let stream = match accept(listener, self.timeout) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // Let the workers that did connect go
+                    remaining.store(0, Ordering::SeqCst);
+                    return Err(e);
+                }
+            };
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            let (queue, remaining, tx, job) = (queue.clone(), remaining.clone(), tx.clone(), self.job.clone());
+
+            threads.push(thread::spawn(move || {
+                if let Err(e) = serve(stream, &job, &queue, &remaining, &tx) {
+                    let _ = tx.send(Err(e));
+                }
+            }));
+        }
+        drop(tx);
+
+        let mut samples = vec![vec![Sample::new(); self.job.height]; self.job.width];
+        let mut live = workers;
+        while remaining.load(Ordering::SeqCst) > 0 {
+            let message = rx.recv()
+                .map_err(|_| Error::other("all workers disconnected before the render completed"))?;
+            let (tile, result) = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    live -= 1;
+                    if live == 0 {
+                        return Err(e);
+                    }
+                    continue;
+                }
+            };
+
+            for (dx, column) in result.iter().enumerate() {
+                for (dy, s) in column.iter().enumerate() {
+                    samples[tile.x + dx][tile.y + dy].merge(s);
+                }
+            }
+
+            remaining.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        // Workers are told to stop before returning, so they don't see the connection drop
+        for thread in threads {
+            let _ = thread.join();
+        }
+
+        Ok(samples)
+    }
+}
+
+// The listener has no accept timeout of its own, so it's polled instead
+fn accept(listener: &TcpListener, timeout: Duration) -> io::Result<TcpStream> {
+    let start = Instant::now();
+    listener.set_nonblocking(true)?;
+
+    let result = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream.set_nonblocking(false).map(|_| stream),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                if start.elapsed() >= timeout {
+                    break Err(Error::new(ErrorKind::TimedOut, "timed out waiting for workers to connect"));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => break Err(e),
+        }
+    };
+
+    listener.set_nonblocking(false)?;
+    result
+}
+
+type Message = io::Result<(Tile, Vec<Vec<Sample>>)>;
+
+fn serve(stream: TcpStream,
+         job: &Job,
+         queue: &Mutex<Vec<Tile>>,
+         remaining: &AtomicUsize,
+         tx: &Sender<Message>)
+         -> io::Result<()> {
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    write_bytes(&mut w, &job.scene)?;
+    write_u64(&mut w, job.config.max_bounces as u64)?;
+    write_u64(&mut w, job.config.adapt as u64)?;
+    write_u64(&mut w, job.width as u64)?;
+    write_u64(&mut w, job.height as u64)?;
+    write_u64(&mut w, job.seed as u64)?;
+
+    // Keep polling until every tile is merged, a failing worker may still return its tile
+    while remaining.load(Ordering::SeqCst) > 0 {
+        let tile = match queue.lock().unwrap().pop() {
+            Some(tile) => tile,
+            None => {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        };
+
+        match exchange(&mut r, &mut w, &tile) {
+            Ok(samples) => {
+                let _ = tx.send(Ok((tile, samples)));
+            }
+            Err(e) => {
+                queue.lock().unwrap().push(tile);
+                return Err(e);
+            }
+        }
+    }
+
+    write_u64(&mut w, DONE)?;
+    w.flush()
+}
+
+fn exchange<R: io::Read, W: Write>(r: &mut R, w: &mut W, tile: &Tile) -> io::Result<Vec<Vec<Sample>>> {
+    write_u64(w, TILE)?;
+    for n in &[tile.x, tile.y, tile.width, tile.height, tile.samples, tile.pass] {
+        write_u64(w, *n as u64)?;
+    }
+    w.flush()?;
+
+    let samples = read_samples(r)?;
+    if samples.len() != tile.width || samples.iter().any(|c| c.len() != tile.height) {
+        return Err(Error::new(ErrorKind::InvalidData, "worker returned a buffer of the wrong size"));
+    }
+
+    Ok(samples)
+}
+
+// Connects to a coordinator and renders tiles until told to stop. The
+// closure receives the job and a tile and returns the samples of the tile,
+// usually by building the scene once and calling `Tile::render`.
+pub fn work<A: ToSocketAddrs, F>(addr: A, mut render: F) -> io::Result<()>
+    where F: FnMut(&Job, &Tile) -> io::Result<Vec<Vec<Sample>>>
+{
+    let stream = TcpStream::connect(addr)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    let job = Job {
+        scene: read_bytes(&mut r)?,
+        config: SamplerConfiguration {
+            max_bounces: read_u64(&mut r)? as usize,
+            adapt: read_u64(&mut r)? as usize,
+        },
+        width: read_u64(&mut r)? as usize,
+        height: read_u64(&mut r)? as usize,
+        seed: read_u64(&mut r)? as u32,
+    };
+
+    while read_u64(&mut r)? == TILE {
+        let tile = Tile {
+            x: read_u64(&mut r)? as usize,
+            y: read_u64(&mut r)? as usize,
+            width: read_u64(&mut r)? as usize,
+            height: read_u64(&mut r)? as usize,
+            samples: read_u64(&mut r)? as usize,
+            pass: read_u64(&mut r)? as usize,
+        };
+
+        write_samples(&mut w, &render(&job, &tile)?)?;
+        w.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coordinator, Job, work};
+    use sampler::{Sampler, SamplerConfiguration};
+    use sample::Sample;
+    use energy::Energy;
+    use direction::Direction;
+    use camera::Camera;
+    use scene::Scene;
+    use quad::Quad;
+    use material::Lambert;
+    use surface::Surface;
+    use std::io::ErrorKind;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    fn tiled() -> Coordinator {
+        let mut coordinator = Coordinator::new(Job {
+            scene: b"scene".to_vec(),
+            config: SamplerConfiguration {
+                max_bounces: 4,
+                adapt: 1,
+            },
+            width: 10,
+            height: 7,
+            seed: 3,
+        });
+        coordinator.tile = 3;
+        coordinator.samples = 2;
+        coordinator.passes = 2;
+        coordinator
+    }
+
+    #[test]
+    fn distributed_merges_tiles() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let coordinator = tiled();
+
+        let workers: Vec<_> = (0..3)
+            .map(|_| {
+                thread::spawn(move || {
+                    work(addr, |job, tile| {
+                        assert_eq!(b"scene".to_vec(), job.scene);
+                        let mut samples = vec![vec![Sample::new(); tile.height]; tile.width];
+                        for (dx, column) in samples.iter_mut().enumerate() {
+                            for (dy, s) in column.iter_mut().enumerate() {
+                                for _ in 0..tile.samples {
+                                    let v = (tile.x + dx + tile.y + dy) as f64;
+                                    s.add(&Energy { x: v, y: v, z: v }, &Energy { x: 1.0, y: 1.0, z: 1.0 }, &Direction { x: 0.0, y: 0.0, z: 1.0 });
+                                }
+                            }
+                        }
+                        Ok(samples)
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        let samples = coordinator.run(&listener, 3).unwrap();
+        for w in workers {
+            w.join().unwrap();
+        }
+
+        assert_eq!(10, samples.len());
+        for (x, column) in samples.iter().enumerate() {
+            assert_eq!(7, column.len());
+            for (y, sample) in column.iter().enumerate() {
+                assert_eq!(4, sample.count);
+                assert_eq!((x + y) as f64, sample.mean().x);
+            }
+        }
+    }
+
+    // Renders every tile of the coordinator with a fresh sampler
+    fn render(coordinator: &Coordinator, worker: Option<::std::net::SocketAddr>) -> Vec<Vec<Sample>> {
+        let lambert = Lambert::new(0.8, 0.5, 0.2);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(&lambert))];
+        let scene = Scene::new(&surfaces);
+        let camera = Camera::new(coordinator.job.width, coordinator.job.height, 0.050, 0.024, 4.0);
+        let mut sampler = Sampler::new(&camera, &scene, coordinator.job.config.clone());
+
+        match worker {
+            Some(addr) => {
+                work(addr, |job, tile| tile.render(&mut sampler, job.seed)).unwrap();
+                Vec::new()
+            }
+            None => {
+                let mut samples = vec![vec![Sample::new(); camera.height]; camera.width];
+                for tile in coordinator.tiles() {
+                    for (dx, column) in tile.render(&mut sampler, coordinator.job.seed).unwrap().iter().enumerate() {
+                        for (dy, s) in column.iter().enumerate() {
+                            samples[tile.x + dx][tile.y + dy].merge(s);
+                        }
+                    }
+                }
+                samples
+            }
+        }
+    }
+
+    #[test]
+    fn distributed_renders_tiles() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let coordinator = tiled();
+
+        let workers: Vec<_> = (0..2)
+            .map(|_| thread::spawn(move || render(&tiled(), Some(addr))))
+            .collect();
+        let samples = coordinator.run(&listener, 2).unwrap();
+        for w in workers {
+            w.join().unwrap();
+        }
+
+        // Tiles are seeded by their position, so the workers match a local render
+        let local = render(&coordinator, None);
+        for x in 0..10 {
+            for y in 0..7 {
+                assert_eq!(4, samples[x][y].count);
+                assert_eq!(local[x][y].count, samples[x][y].count);
+                assert_eq!(local[x][y].red, samples[x][y].red);
+                assert_eq!(local[x][y].blue, samples[x][y].blue);
+            }
+        }
+        assert!(local[5][3].red > 0.0);
+    }
+
+    #[test]
+    fn distributed_requeues_hung_tiles() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut coordinator = tiled();
+        coordinator.timeout = Duration::from_millis(200);
+
+        // Connects but never answers, its tile has to go to the other worker
+        let hung = TcpStream::connect(addr).unwrap();
+        let worker = thread::spawn(move || render(&tiled(), Some(addr)));
+        let samples = coordinator.run(&listener, 2).unwrap();
+        worker.join().unwrap();
+        drop(hung);
+
+        assert_eq!(10, samples.len());
+        for column in &samples {
+            assert_eq!(7, column.len());
+            assert!(column.iter().all(|s| s.count == 4));
+        }
+    }
+
+    #[test]
+    fn distributed_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut coordinator = tiled();
+        coordinator.timeout = Duration::from_millis(50);
+        assert_eq!(ErrorKind::TimedOut, coordinator.run(&listener, 1).unwrap_err().kind());
+
+        // The only worker disconnects, its error is what the render fails with
+        let gone = TcpStream::connect(addr).unwrap();
+        drop(gone);
+        assert!(coordinator.run(&listener, 1).is_err());
+
+        coordinator.tile = 0;
+        assert_eq!(ErrorKind::InvalidInput, coordinator.run(&listener, 1).unwrap_err().kind());
+    }
+}
@@ -0,0 +1,128 @@
+// Piecewise constant distribution over [0, 1), for importance sampling tabulated
+// functions such as measured materials
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    pub func: Vec<f64>,
+    cdf: Vec<f64>,
+    pub integral: f64,
+}
+
+impl Distribution {
+    // Negative values are treated as zero. Without any positive value the
+    // distribution is uniform.
+    pub fn new(func: Vec<f64>) -> Distribution {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            let last = cdf[i];
+            cdf.push(last + func[i].max(0.0) / n as f64);
+        }
+
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0.0 { *c / integral } else { i as f64 / n as f64 };
+        }
+
+        Distribution {
+            func: func,
+            cdf: cdf,
+            integral: integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    // Maps `u` in [0, 1) to a position with density `pdf`, and the index of its piece
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // Last piece starting at or below u, which skips empty pieces
+        let (mut i, mut end) = (0, self.count());
+        while end - i > 1 {
+            let mid = (i + end) / 2;
+            if self.cdf[mid] <= u {
+                i = mid;
+            } else {
+                end = mid;
+            }
+        }
+
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let t = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        ((i as f64 + t) / self.count() as f64, self.pdf_at(i), i)
+    }
+
+    // Density of the position `x` in [0, 1)
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.pdf_at(i)
+    }
+
+    fn pdf_at(&self, i: usize) -> f64 {
+        (self.cdf[i + 1] - self.cdf[i]) * self.count() as f64
+    }
+}
+
+// Piecewise constant distribution over [0, 1)², from a table of `width` columns
+// and `height` rows. Picks a row by its total, then a column within it.
+#[derive(Debug, Clone)]
+pub struct Distribution2 {
+    rows: Vec<Distribution>,
+    marginal: Distribution,
+}
+
+impl Distribution2 {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2 {
+        let rows: Vec<Distribution> = (0..height).map(|y| Distribution::new(func[y * width..(y + 1) * width].to_vec())).collect();
+        let marginal = Distribution::new(rows.iter().map(|r| r.integral).collect());
+
+        Distribution2 {
+            rows: rows,
+            marginal: marginal,
+        }
+    }
+
+    // Maps `u` and `v` in [0, 1) to a position with density `pdf`
+    pub fn sample(&self, u: f64, v: f64) -> (f64, f64, f64) {
+        let (y, row_pdf, row) = self.marginal.sample(v);
+        let (x, column_pdf, _) = self.rows[row].sample(u);
+        (x, y, row_pdf * column_pdf)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Distribution, Distribution2};
+
+    #[test]
+    fn distribution_sampling() {
+        let d = Distribution::new(vec![1.0, 0.0, 3.0, 0.0]);
+        assert!((d.integral - 1.0).abs() < 1e-9);
+
+        let (x, pdf, i) = d.sample(0.1);
+        assert_eq!(i, 0);
+        assert!((x - 0.1).abs() < 1e-9 && (pdf - 1.0).abs() < 1e-9);
+
+        // Empty pieces are never chosen
+        let (x, pdf, i) = d.sample(0.25);
+        assert_eq!(i, 2);
+        assert!((x - 0.5).abs() < 1e-9 && (pdf - 3.0).abs() < 1e-9);
+        assert_eq!(d.pdf(0.3), 0.0);
+
+        let uniform = Distribution::new(vec![0.0, 0.0]);
+        assert!((uniform.pdf(0.7) - 1.0).abs() < 1e-9);
+
+        // Only the bright corner of the table is sampled
+        let d = Distribution2::new(&[0.0, 0.0, 0.0, 5.0], 2, 2);
+        let (x, y, pdf) = d.sample(0.3, 0.9);
+        assert!(x >= 0.5 && y >= 0.5 && (pdf - 4.0).abs() < 1e-9);
+        assert!((d.pdf(x, y) - pdf).abs() < 1e-9);
+        assert_eq!(d.pdf(0.2, 0.7), 0.0);
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{self, Read, Write};
+use sample::Sample;
+use vector3::Vector3;
+
+// Minimal big endian encoding used for checkpoints and the distributed renderer
+
+pub fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+    let mut buf = [0u8; 8];
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = (n >> (56 - i * 8)) as u8;
+    }
+    w.write_all(&buf)
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(buf.iter().fold(0, |n, b| (n << 8) | *b as u64))
+}
+
+pub fn write_f64<W: Write>(w: &mut W, n: f64) -> io::Result<()> {
+    write_u64(w, n.to_bits())
+}
+
+pub fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(r)?))
+}
+
+pub fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_u64(w, bytes.len() as u64)?;
+    w.write_all(bytes)
+}
+
+pub fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u64(r)? as usize;
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn write_vector<W: Write>(w: &mut W, v: &Vector3) -> io::Result<()> {
+    write_f64(w, v.x)?;
+    write_f64(w, v.y)?;
+    write_f64(w, v.z)
+}
+
+pub fn read_vector<R: Read>(r: &mut R) -> io::Result<Vector3> {
+    Ok(Vector3 {
+        x: read_f64(r)?,
+        y: read_f64(r)?,
+        z: read_f64(r)?,
+    })
+}
+
+pub fn write_samples<W: Write>(w: &mut W, samples: &Vec<Vec<Sample>>) -> io::Result<()> {
+    write_u64(w, samples.len() as u64)?;
+    write_u64(w, samples.first().map_or(0, |c| c.len()) as u64)?;
+
+    for column in samples {
+        for s in column {
+            write_f64(w, s.red)?;
+            write_f64(w, s.green)?;
+            write_f64(w, s.blue)?;
+            write_u64(w, s.count as u64)?;
+            write_f64(w, s.square)?;
+            write_vector(w, &s.albedo)?;
+            write_vector(w, &s.normal)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_samples<R: Read>(r: &mut R) -> io::Result<Vec<Vec<Sample>>> {
+    let width = read_u64(r)? as usize;
+    let height = read_u64(r)? as usize;
+    let mut samples = Vec::with_capacity(width);
+
+    for _ in 0..width {
+        let mut column = Vec::with_capacity(height);
+        for _ in 0..height {
+            column.push(Sample {
+                red: read_f64(r)?,
+                green: read_f64(r)?,
+                blue: read_f64(r)?,
+                count: read_u64(r)? as usize,
+                square: read_f64(r)?,
+                albedo: read_vector(r)?,
+                normal: read_vector(r)?,
+            });
+        }
+        samples.push(column);
+    }
+
+    Ok(samples)
+}
+
+// FNV-1a, stable across runs unlike the std hasher
+pub fn fnv(bytes: &[u8]) -> u64 {
+    fnv_from(0xcbf29ce484222325, bytes)
+}
+
+// Continues the hash `h` over more bytes
+pub fn fnv_from(h: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(h, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+// FNV-1a over the little endian bits of the colors, to summarize images once
+pub fn fnv_colors<'a, I: Iterator<Item = &'a Vector3>>(colors: I) -> u64 {
+    colors.fold(fnv(&[]), |h, c| [c.x, c.y, c.z].iter().fold(h, |h, n| fnv_from(h, &n.to_bits().to_le_bytes())))
+}
+
+// FNV-1a over the parameters of a scene object, fed in one at a time
+#[derive(Clone, Copy)]
+pub struct Digest(u64);
+
+impl Digest {
+    // Starts from the name of the type, so different objects with the same values differ
+    pub fn new(name: &str) -> Digest {
+        Digest(fnv(name.as_bytes()))
+    }
+
+    pub fn value(self, n: f64) -> Digest {
+        Digest(fnv_from(self.0, &n.to_bits().to_le_bytes()))
+    }
+
+    pub fn values(self, ns: &[f64]) -> Digest {
+        ns.iter().fold(self, |d, n| d.value(*n))
+    }
+
+    pub fn vector(self, v: &Vector3) -> Digest {
+        self.values(&[v.x, v.y, v.z])
+    }
+
+    // Folds in the digest of a part, such as a texture or a wrapped material
+    pub fn with(self, h: u64) -> Digest {
+        Digest(fnv_from(self.0, &h.to_le_bytes()))
+    }
+
+    pub fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_f64, read_u64, write_f64, write_u64};
+
+    #[test]
+    fn encode_roundtrip() {
+        let mut buf = Vec::new();
+        write_u64(&mut buf, 0x0102030405060708).unwrap();
+        write_f64(&mut buf, -1.25).unwrap();
+
+        assert_eq!(&[1, 2, 3, 4, 5, 6, 7, 8], &buf[..8]);
+
+        let mut r = &buf[..];
+        assert_eq!(0x0102030405060708, read_u64(&mut r).unwrap());
+        assert_eq!(-1.25, read_f64(&mut r).unwrap());
+    }
+}
@@ -1,5 +1,5 @@
 use vector3::Vector3;
-use rand::{Rng, ThreadRng};
+use rand::{Rng, XorShiftRng};
 
 pub type Energy = Vector3;
 
@@ -12,7 +12,7 @@ impl Energy {
         self * n
     }
 
-    pub fn random_gain(&self, rng: &mut ThreadRng) -> Option<Energy> {
+    pub fn random_gain(&self, rng: &mut XorShiftRng) -> Option<Energy> {
         let max = self.max();
 
         if rng.gen_range(0.0, 1.0) > max {
@@ -0,0 +1,218 @@
+use energy::Energy;
+use direction::Direction;
+use distribution::Distribution2;
+use encode::fnv_colors;
+use openexr;
+use sample::luminance;
+use image::{ImageError, ImageResult};
+use image::hdr::HDRDecoder;
+use rand::{Rng, XorShiftRng};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
+use std::f64::consts::PI;
+
+// Light from all around the scene, an equirectangular image with the top row
+// straight up. Directions are importance sampled by the luminance of the image,
+// so small bright areas such as the sun are found.
+pub struct Environment {
+    pub width: usize,
+    pub height: usize,
+    pub rotation: f64, // Around the vertical axis, in radians
+    pub intensity: f64,
+    pixels: Vec<Energy>,
+    distribution: Distribution2,
+    digest: u64, // Of the pixels, taken once as they are large
+}
+
+impl Environment {
+    // OpenEXR files by their extension, otherwise Radiance HDR
+    pub fn open(path: &Path) -> ImageResult<Environment> {
+        let (width, height, pixels) = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("exr")) {
+            openexr::read(path)?
+        } else {
+            let decoder = HDRDecoder::new(BufReader::new(File::open(path).map_err(ImageError::IoError)?))?;
+            let (width, height) = (decoder.metadata().width as usize, decoder.metadata().height as usize);
+            let pixels = decoder.read_image_hdr()?
+                .iter()
+                .map(|p| {
+                    Energy {
+                        x: p[0] as f64,
+                        y: p[1] as f64,
+                        z: p[2] as f64,
+                    }
+                })
+                .collect();
+            (width, height, pixels)
+        };
+
+        if width == 0 || height == 0 {
+            return Err(ImageError::IoError(Error::new(ErrorKind::InvalidData, "empty environment map")));
+        }
+        Ok(Environment::new(width, height, pixels))
+    }
+
+    // Pixels in rows from the top, at least one
+    pub fn new(width: usize, height: usize, pixels: Vec<Energy>) -> Environment {
+        assert!(width > 0 && height > 0, "empty environment map");
+        assert_eq!(width * height, pixels.len());
+
+        // Rows near the poles cover less of the sphere
+        let weights: Vec<f64> = pixels.iter()
+            .enumerate()
+            .map(|(i, p)| luminance(p) * (PI * ((i / width) as f64 + 0.5) / height as f64).sin())
+            .collect();
+
+        Environment {
+            width: width,
+            height: height,
+            rotation: 0.0,
+            intensity: 1.0,
+            distribution: Distribution2::new(&weights, width, height),
+            digest: fnv_colors(pixels.iter()),
+            pixels: pixels,
+        }
+    }
+
+    pub fn radiance(&self, dir: &Direction) -> Energy {
+        let (u, v) = self.uv(dir);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x].amplified(self.intensity)
+    }
+
+    // Samples a direction towards the environment, its radiance and solid angle density
+    pub fn sample(&self, rng: &mut XorShiftRng) -> (Direction, Energy, f64) {
+        let (u, v, pdf) = self.distribution.sample(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+        let (theta, phi) = (PI * v, 2.0 * PI * u + self.rotation);
+        let sin = theta.sin();
+        let dir = Direction {
+            x: sin * phi.cos(),
+            y: theta.cos(),
+            z: sin * phi.sin(),
+        };
+
+        let pdf = if sin > 0.0 { pdf / (2.0 * PI * PI * sin) } else { 0.0 };
+        (dir.clone(), self.radiance(&dir), pdf)
+    }
+
+    // Solid angle density of `sample` choosing `dir`
+    pub fn pdf(&self, dir: &Direction) -> f64 {
+        let (u, v) = self.uv(dir);
+        let sin = (PI * v).sin();
+        if sin <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin)
+    }
+
+    // Hash of the pixels
+    pub fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    fn uv(&self, dir: &Direction) -> (f64, f64) {
+        let u = (dir.z.atan2(dir.x) - self.rotation) / (2.0 * PI);
+        (u - u.floor(), dir.y.clamp(-1.0, 1.0).acos() / PI)
+    }
+}
+
+// The pixels only show up as their hash, as they are large
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Environment {{ width: {}, height: {}, rotation: {}, intensity: {}, pixels: {:x} }}",
+               self.width,
+               self.height,
+               self.rotation,
+               self.intensity,
+               self.digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Environment;
+    use energy::Energy;
+    use direction::Direction;
+    use image::ImageError;
+    use exr::prelude::write_rgb_file;
+    use rand::{SeedableRng, XorShiftRng};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{ErrorKind, Write};
+    use std::path::Path;
+    use std::f64::consts::PI;
+
+    // A dim sky with a small bright sun
+    fn sky() -> Environment {
+        let (width, height) = (32, 16);
+        let pixels = (0..width * height)
+            .map(|i| if i == 4 * width + 5 { Energy { x: 5000.0, y: 5000.0, z: 5000.0 } } else { Energy { x: 1.0, y: 1.0, z: 1.0 } })
+            .collect();
+        Environment::new(width, height, pixels)
+    }
+
+    #[test]
+    fn environment_sampling() {
+        let mut env = sky();
+        env.rotation = 1.0;
+        let mut rng = XorShiftRng::from_seed([8, 1, 8, 1]);
+
+        // Estimates the total power arriving from all around
+        let n = 20000;
+        let mut total = 0.0;
+        let mut sun = 0;
+        for _ in 0..n {
+            let (dir, radiance, pdf) = env.sample(&mut rng);
+            assert!((env.pdf(&dir) - pdf).abs() < 1e-6 * pdf);
+            total += radiance.x / pdf;
+            if radiance.x > 1.0 {
+                sun += 1;
+            }
+        }
+
+        // Sphere area of the sky plus the solid angle of the sun pixel
+        let row = ((PI * 4.0 / 16.0).cos() - (PI * 5.0 / 16.0).cos()) * (2.0 * PI / 32.0);
+        let expected = 4.0 * PI + 4999.0 * row;
+        assert!((total / n as f64 - expected).abs() < 0.02 * expected);
+        assert!(sun > n / 2);
+
+        let up = env.radiance(&Direction { x: 0.0, y: 1.0, z: 0.0 });
+        assert_eq!(up.x, 1.0);
+    }
+
+    #[test]
+    fn environment_exr() {
+        // Rows from the top, values far above one survive
+        let path = env::temp_dir().join("environment_exr.EXR");
+        write_rgb_file(&path, 2, 2, |x, y| (x as f32, 100.0 * y as f32, 0.5f32)).unwrap();
+        let sky = Environment::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((2, 2), (sky.width, sky.height));
+        let up = sky.radiance(&Direction { x: 0.0, y: 1.0, z: 0.0 });
+        let down = sky.radiance(&Direction { x: 0.0, y: -1.0, z: 0.0 });
+        assert_eq!((0.0, 0.5), (up.y, up.z));
+        assert_eq!(100.0, down.y);
+
+        match Environment::open(Path::new("missing.exr")) {
+            Err(ImageError::IoError(ref e)) if e.kind() == ErrorKind::NotFound => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn environment_empty() {
+        let path = env::temp_dir().join("environment_empty.hdr");
+        File::create(&path).unwrap().write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 0 +X 0\n").unwrap();
+        let empty = Environment::open(&path);
+        fs::remove_file(&path).unwrap();
+        match empty {
+            Err(ImageError::IoError(ref e)) if e.kind() == ErrorKind::InvalidData => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+}
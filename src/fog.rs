@@ -0,0 +1,142 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, pass, black};
+use medium::Medium;
+use surface::Geometry;
+use rand::XorShiftRng;
+
+// Invisible bounds of a participating medium, for smoke, a hazy room or murky
+// water. Rays pass the surface unchanged and scatter through the medium inside,
+// shadow rays are dimmed by it. Needs closed surfaces.
+#[derive(Debug)]
+pub struct Fog {
+    pub medium: Medium,
+}
+
+impl Fog {
+    pub fn new(medium: Medium) -> Fog {
+        Fog { medium: medium }
+    }
+
+    // White haze scattering slightly forward, `density` events per unit distance
+    pub fn haze(density: f64) -> Fog {
+        Fog::new(Medium::new(Energy { x: 0.0, y: 0.0, z: 0.0 },
+                             Energy { x: density, y: density, z: density },
+                             0.3))
+    }
+
+    // Dark smoke absorbing most of the light it doesn't scatter
+    pub fn smoke(density: f64) -> Fog {
+        Fog::new(Medium::new(Energy { x: 0.8 * density, y: 0.8 * density, z: 0.8 * density },
+                             Energy { x: 0.2 * density, y: 0.2 * density, z: 0.2 * density },
+                             0.0))
+    }
+
+    // Green-blue water, absorbing red first and strongly forward scattering
+    pub fn water(turbidity: f64) -> Fog {
+        Fog::new(Medium::new(Energy { x: 0.45, y: 0.06, z: 0.03 },
+                             Energy { x: 0.1 * turbidity, y: 0.12 * turbidity, z: 0.12 * turbidity },
+                             0.9))
+    }
+}
+
+impl Material for Fog {
+    fn sample(&self, _: &Geometry, inc: &Direction, _: f64, _: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        pass(inc)
+    }
+
+    fn evaluate(&self, _: &Geometry, _: &Direction, _: &Direction) -> Energy {
+        black()
+    }
+
+    fn pdf(&self, _: &Geometry, _: &Direction, _: &Direction) -> f64 {
+        0.0
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+
+    fn albedo(&self, _: &Geometry) -> Energy {
+        black()
+    }
+
+    fn transparent(&self) -> bool {
+        true
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Fog").with(self.medium.digest()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fog;
+    use material::{Material, Lambert};
+    use mapping::{Mapped, Mapping};
+    use layered::Coated;
+    use mask::Masked;
+    use sheen::Sheen;
+    use texture::Texture;
+    use scene::Scene;
+    use sphere::Sphere;
+    use surface::Surface;
+    use vector3::Vector3;
+    use direction::Direction;
+    use rand::{SeedableRng, XorShiftRng};
+
+    #[test]
+    fn fog_transmittance() {
+        let fog = Fog::haze(0.5);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(&fog))];
+        let mut scene = Scene::new(&surfaces);
+        let mut rng = XorShiftRng::from_seed([5, 5, 0, 5]);
+        let from = Vector3 { x: 0.0, y: -3.0, z: 0.0 };
+        let up = Direction { x: 0.0, y: 1.0, z: 0.0 };
+
+        // Through the whole sphere, then from inside it
+        let tr = scene.transmittance(&from, &up, 6.0, None, &mut rng);
+        assert!((tr.x - (-0.5f64).exp()).abs() < 1e-6, "{:?}", tr);
+        let tr = scene.transmittance(&Vector3 { x: 0.0, y: 0.0, z: 0.0 }, &up, 6.0, Some(&fog.medium), &mut rng);
+        assert!((tr.x - (-0.25f64).exp()).abs() < 1e-6, "{:?}", tr);
+
+        // The atmosphere reaches up to the lamp, but not beyond the last surface
+        scene.atmosphere = Some(Fog::haze(0.1).medium);
+        let tr = scene.transmittance(&from, &up, 6.0, None, &mut rng);
+        assert!((tr.x - (-0.5f64 - 0.5).exp()).abs() < 1e-6, "{:?}", tr);
+        let tr = scene.transmittance(&from, &up, f64::INFINITY, None, &mut rng);
+        assert!((tr.x - (-0.5f64 - 0.25).exp()).abs() < 1e-6, "{:?}", tr);
+
+        // Opaque surfaces still cast shadows
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(&lambert))];
+        let scene = Scene::new(&surfaces);
+        assert_eq!(scene.transmittance(&from, &up, 6.0, None, &mut rng).x, 0.0);
+    }
+
+    #[test]
+    fn wrapped_fog() {
+        // Wrapping the bounds keeps the medium inside and lets shadow rays through
+        let fog = Fog::haze(0.5);
+        let mapped = Mapped::new(&fog, Mapping::Bump { height: Texture::value(0.0), strength: 1.0 });
+        let coated = Coated::new(&fog);
+        let masked = Masked::two_sided(&fog);
+        let sheen = Sheen::over(&fog, 0.5, 0.5, 0.5, 0.5);
+        let mut rng = XorShiftRng::from_seed([5, 0, 5, 0]);
+        let from = Vector3 { x: 0.0, y: -3.0, z: 0.0 };
+        let up = Direction { x: 0.0, y: 1.0, z: 0.0 };
+
+        let wrapped: [&Material; 4] = [&mapped, &coated, &masked, &sheen];
+        for m in wrapped.iter() {
+            assert!(m.transparent());
+            assert_eq!(m.medium().unwrap().scattering.x, 0.5);
+
+            let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(*m))];
+            let scene = Scene::new(&surfaces);
+            let tr = scene.transmittance(&from, &up, 6.0, None, &mut rng);
+            assert!((tr.x - (-0.5f64).exp()).abs() < 1e-6, "{:?} {:?}", m, tr);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// Monte Carlo estimates shared by the tests of the materials
+use energy::Energy;
+use direction::Direction;
+use material::{Material, black};
+use surface::Geometry;
+use rand::XorShiftRng;
+
+// Mean of `n` estimates
+pub fn mean<F>(n: usize, rng: &mut XorShiftRng, mut estimate: F) -> Energy
+    where F: FnMut(&mut XorShiftRng) -> Energy
+{
+    let mut total = Energy { x: 0.0, y: 0.0, z: 0.0 };
+    for _ in 0..n {
+        total = &total + &estimate(rng);
+    }
+
+    total.amplified(1.0 / n as f64)
+}
+
+// Share of the light arriving along `inc` that `m` scatters, as in a white furnace.
+// Absorbed samples count as black.
+pub fn albedo(m: &Material, g: &Geometry, inc: &Direction, n: usize, rng: &mut XorShiftRng) -> Energy {
+    mean(n, rng, |rng| match m.sample(g, inc, 0.0, rng) {
+        (true, _, strength, _) => strength,
+        _ => black(),
+    })
+}
@@ -0,0 +1,233 @@
+use energy::Energy;
+use vector3::Vector3;
+use matrix4::Matrix4;
+use ray3::Ray3;
+use encode::{fnv, fnv_from, fnv_colors, Digest};
+use spectrum::{black_body_xyz, xyz_to_srgb};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+
+// Dense voxels filling the unit cube around the origin, placed like a surface.
+// Densities are interpolated between voxel centers and zero outside the cube.
+pub struct Grid {
+    pub dims: [usize; 3],
+    pub max: f64, // Highest density, bounding the interpolated ones
+    density: Vec<f64>,
+    emission: Option<Vec<Energy>>,
+    pos: Matrix4,
+    inverse: Matrix4,
+    digest: u64, // Of the voxels, taken once as they are large
+}
+
+impl Grid {
+    // Densities with x varying fastest, then y and z
+    pub fn new(dims: [usize; 3], density: Vec<f64>) -> Grid {
+        assert_eq!(dims[0] * dims[1] * dims[2], density.len());
+
+        Grid {
+            dims: dims,
+            max: density.iter().fold(0.0f64, |m, &d| m.max(d)),
+            digest: density.iter().fold(fnv(&[]), |h, d| fnv_from(h, &d.to_bits().to_le_bytes())),
+            density: density,
+            emission: None,
+            pos: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    // Three little endian i32 with the number of voxels along x, y and z, then
+    // a f32 density for each voxel, optionally followed by a f32 temperature in
+    // kelvin for each voxel. `strength` scales the emission, as for `fire`.
+    pub fn open(path: &Path, strength: f64) -> io::Result<Grid> {
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "not a voxel grid file");
+        let word = |i: usize| bytes[i * 4..i * 4 + 4].iter().rev().fold(0u32, |n, b| (n << 8) | *b as u32);
+
+        if bytes.len() < 12 {
+            return Err(invalid());
+        }
+        let mut dims = [0; 3];
+        for (i, d) in dims.iter_mut().enumerate() {
+            let n = word(i) as i32;
+            if n <= 0 {
+                return Err(invalid());
+            }
+            *d = n as usize;
+        }
+
+        // Sizes too large to address can't match the length of the file
+        let n = dims[0].checked_mul(dims[1]).and_then(|n| n.checked_mul(dims[2])).ok_or_else(invalid)?;
+        let words = bytes.len() / 4 - 3;
+        if bytes.len() % 4 != 0 || (words != n && Some(words) != n.checked_mul(2)) {
+            return Err(invalid());
+        }
+        let values: Vec<f64> = (3..3 + words).map(|i| f32::from_bits(word(i)) as f64).collect();
+
+        let grid = Grid::new(dims, values[..n].iter().map(|&d| d.max(0.0)).collect());
+        if words == 2 * n {
+            return Ok(grid.fire(&values[n..], strength));
+        }
+        Ok(grid)
+    }
+
+    // Glows like a black body at the temperature of each voxel, for fire and
+    // embers. `strength` scales the radiance in W/(m² sr) to the scene.
+    pub fn fire(mut self, temperature: &[f64], strength: f64) -> Grid {
+        assert_eq!(self.density.len(), temperature.len());
+        let emission: Vec<Energy> = temperature.iter()
+            .map(|&t| {
+                let (x, y, z) = black_body_xyz(t);
+                let (r, g, b) = xyz_to_srgb(x, y, z);
+                Energy { x: r, y: g, z: b }.amplified(strength)
+            })
+            .collect();
+        self.digest ^= fnv_colors(emission.iter());
+        self.emission = Some(emission);
+        self
+    }
+
+    pub fn placed(mut self, pos: Matrix4) -> Grid {
+        self.inverse = pos.inverse();
+        self.pos = pos;
+        self
+    }
+
+    // Hash of the voxels and where they are placed
+    pub fn digest(&self) -> u64 {
+        Digest::new("Grid").with(self.digest).with(self.pos.digest()).finish()
+    }
+
+    pub fn density(&self, point: &Vector3) -> f64 {
+        self.interpolate(point, |i| self.density[i], 0.0, |a, b, t| a + (b - a) * t)
+    }
+
+    // Black body radiance at `point`, if the grid has temperatures
+    pub fn emission(&self, point: &Vector3) -> Option<Energy> {
+        self.emission.as_ref().map(|emission| {
+            self.interpolate(point,
+                             |i| emission[i].clone(),
+                             Energy { x: 0.0, y: 0.0, z: 0.0 },
+                             |a, b, t| a.lerp(&b, t))
+        })
+    }
+
+    // Where `ray` is inside the cube, from before `dist`
+    pub fn span(&self, ray: &Ray3, dist: f64) -> Option<(f64, f64)> {
+        let origin = self.inverse.mult_point(&ray.origin);
+        let dir = self.inverse.mult_dist(&ray.direction);
+        let (mut near, mut far) = (0.0f64, dist);
+
+        for &(o, d) in &[(origin.x, dir.x), (origin.y, dir.y), (origin.z, dir.z)] {
+            if d == 0.0 {
+                if o.abs() > 0.5 {
+                    return None;
+                }
+                continue;
+            }
+            let (a, b) = ((-0.5 - o) / d, (0.5 - o) / d);
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+
+        if near < far && near < f64::INFINITY { Some((near, far)) } else { None }
+    }
+
+    // Trilinear interpolation between the voxel centers around `point`
+    fn interpolate<T, F, L>(&self, point: &Vector3, at: F, zero: T, lerp: L) -> T
+        where F: Fn(usize) -> T,
+              L: Fn(T, T, f64) -> T
+    {
+        let p = self.inverse.mult_point(point);
+        let local = [p.x, p.y, p.z];
+        if local.iter().any(|c| c.abs() > 0.5) {
+            return zero;
+        }
+
+        let mut cell = [(0, 0, 0.0); 3];
+        for k in 0..3 {
+            let n = self.dims[k];
+            let x = ((local[k] + 0.5) * n as f64 - 0.5).max(0.0).min((n - 1) as f64);
+            let i = x as usize;
+            cell[k] = (i, (i + 1).min(n - 1), x - i as f64);
+        }
+
+        let (nx, ny) = (self.dims[0], self.dims[1]);
+        let index = |x: usize, y: usize, z: usize| (z * ny + y) * nx + x;
+        let ((x0, x1, tx), (y0, y1, ty), (z0, z1, tz)) = (cell[0], cell[1], cell[2]);
+        let row = |y: usize, z: usize| lerp(at(index(x0, y, z)), at(index(x1, y, z)), tx);
+        let plane = |z: usize| lerp(row(y0, z), row(y1, z), ty);
+        lerp(plane(z0), plane(z1), tz)
+    }
+}
+
+// The voxels only show up as their hash, as they are large
+impl fmt::Debug for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Grid {{ dims: {:?}, pos: {:?}, voxels: {:x} }}",
+               self.dims,
+               self.pos,
+               self.digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Grid;
+    use matrix4::Matrix4;
+    use ray3::Ray3;
+    use vector3::Vector3;
+    use direction::Direction;
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::io::{ErrorKind, Write};
+
+    fn write_words(path: &Path, words: &[u32]) {
+        let mut file = File::create(path).unwrap();
+        for w in words {
+            file.write_all(&(0..4).map(|i| (w >> (i * 8)) as u8).collect::<Vec<u8>>()).unwrap();
+        }
+    }
+
+    #[test]
+    fn grid_file() {
+        // Two by two by one voxels, hot where dense
+        let path = env::temp_dir().join("grid_file.vol");
+        let words: Vec<u32> = vec![2, 2, 1]
+            .into_iter()
+            .chain([0.0f32, 1.0, 2.0, 3.0, 300.0, 300.0, 300.0, 2000.0].iter().map(|v| v.to_bits()))
+            .collect();
+        write_words(&path, &words);
+        let grid = Grid::open(&path, 1.0).unwrap();
+
+        // Sizes whose product overflows are turned away
+        write_words(&path, &[0x7fffffff, 0x7fffffff, 0x7fffffff, 0]);
+        let huge = Grid::open(&path, 1.0);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ErrorKind::InvalidData, huge.err().unwrap().kind());
+
+        assert_eq!(grid.dims, [2, 2, 1]);
+        assert_eq!(grid.max, 3.0);
+        let center = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+        assert!((grid.density(&center) - 1.5).abs() < 1e-9);
+        assert!((grid.density(&Vector3 { x: 0.4, y: 0.4, z: 0.0 }) - 3.0).abs() < 1e-9);
+        assert_eq!(grid.density(&Vector3 { x: 0.0, y: 0.0, z: 0.6 }), 0.0);
+        let glow = grid.emission(&Vector3 { x: 0.4, y: 0.4, z: 0.0 }).unwrap();
+        assert!(glow.x > glow.z && glow.x > 100.0 * grid.emission(&Vector3 { x: -0.4, y: -0.4, z: 0.0 }).unwrap().x);
+
+        // Moved along the ray
+        let grid = grid.placed(Matrix4::translation(1.0, 0.0, 0.0));
+        let ray = Ray3 {
+            origin: Vector3 { x: -3.0, y: 0.0, z: 0.0 },
+            direction: Direction { x: 1.0, y: 0.0, z: 0.0 },
+        };
+        let (start, end) = grid.span(&ray, 10.0).unwrap();
+        assert!((start - 3.5).abs() < 1e-9 && (end - 4.5).abs() < 1e-9, "{} {}", start, end);
+        assert!(grid.span(&ray, 3.0).is_none());
+        assert!((grid.density(&Vector3 { x: 1.4, y: 0.4, z: 0.0 }) - 3.0).abs() < 1e-9);
+    }
+}
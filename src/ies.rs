@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::Path;
+use std::f64::consts::PI;
+use encode::Digest;
+
+// Angular distribution of a light fixture from an IES LM-63 photometric file,
+// type C. Vertical angles start at the axis of the fixture, horizontal angles
+// go around it. Intensities are normalized so the brightest direction is one.
+#[derive(Debug, Clone)]
+pub struct Ies {
+    vertical: Vec<f64>, // In radians, increasing
+    horizontal: Vec<f64>,
+    candela: Vec<Vec<f64>>, // Vertical angles for each horizontal angle
+}
+
+impl Ies {
+    pub fn open(path: &Path) -> io::Result<Ies> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Ies::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Ies> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "not an IES LM-63 file");
+
+        // Keywords until the tilt line, then only numbers
+        let mut lines = text.lines();
+        let tilt = lines.find(|l| l.trim().starts_with("TILT=")).ok_or_else(invalid)?;
+        let rest = lines.collect::<Vec<_>>().join(" ");
+        let mut numbers = rest.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f64>());
+        let mut next = || numbers.next().and_then(|n| n.ok()).ok_or_else(invalid);
+
+        if tilt.trim() == "TILT=INCLUDE" {
+            // Lamp to luminaire geometry, then pairs of angles and factors
+            next()?;
+            let n = next()? as usize;
+            for _ in 0..2 * n {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let (nv, nh) = (next()? as usize, next()? as usize);
+        let photometric = next()?;
+        if nv == 0 || nh == 0 || photometric != 1.0 {
+            return Err(invalid());
+        }
+        // Units, width, length, height, ballast factor, future use and watts
+        for _ in 0..7 {
+            next()?;
+        }
+
+        let mut vertical = Vec::with_capacity(nv);
+        for _ in 0..nv {
+            vertical.push(next()?.to_radians());
+        }
+        let mut horizontal = Vec::with_capacity(nh);
+        for _ in 0..nh {
+            horizontal.push(next()?.to_radians());
+        }
+        let mut candela = Vec::with_capacity(nh);
+        for _ in 0..nh {
+            let mut row = Vec::with_capacity(nv);
+            for _ in 0..nv {
+                row.push(next()? * multiplier);
+            }
+            candela.push(row);
+        }
+
+        let max = candela.iter().flat_map(|row| row.iter()).fold(0.0f64, |m, &c| m.max(c));
+        if max <= 0.0 {
+            return Err(invalid());
+        }
+        for c in candela.iter_mut().flat_map(|row| row.iter_mut()) {
+            *c /= max;
+        }
+
+        Ok(Ies {
+            vertical: vertical,
+            horizontal: horizontal,
+            candela: candela,
+        })
+    }
+
+    pub fn digest(&self) -> u64 {
+        let d = Digest::new("Ies").values(&self.vertical).values(&self.horizontal);
+        self.candela.iter().fold(d, |d, c| d.values(c)).finish()
+    }
+
+    // Relative intensity at `theta` from the axis and `phi` around it
+    pub fn intensity(&self, theta: f64, phi: f64) -> f64 {
+        let (first, last) = (self.vertical[0], self.vertical[self.vertical.len() - 1]);
+        if theta < first || theta > last {
+            return 0.0;
+        }
+
+        // The last horizontal angle tells the symmetry of the fixture
+        let mut phi = phi % (2.0 * PI);
+        if phi < 0.0 {
+            phi += 2.0 * PI;
+        }
+        let end = self.horizontal[self.horizontal.len() - 1];
+        if end < PI * 1.5 && phi > PI {
+            phi = 2.0 * PI - phi;
+        }
+        if end < PI * 0.75 && phi > PI / 2.0 {
+            phi = PI - phi;
+        }
+
+        let (h, s) = interval(&self.horizontal, phi);
+        let (v, t) = interval(&self.vertical, theta);
+        let at = |h: usize| {
+            let row = &self.candela[h];
+            row[v] + (row[(v + 1).min(row.len() - 1)] - row[v]) * t
+        };
+        at(h) + (at((h + 1).min(self.horizontal.len() - 1)) - at(h)) * s
+    }
+}
+
+// Index of the interval of `angles` holding `x` and the position within it
+fn interval(angles: &[f64], x: f64) -> (usize, f64) {
+    if angles.len() == 1 || x <= angles[0] {
+        return (0, 0.0);
+    }
+
+    match angles.iter().position(|&a| a > x) {
+        Some(i) => (i - 1, (x - angles[i - 1]) / (angles[i] - angles[i - 1])),
+        None => (angles.len() - 1, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ies;
+    use std::f64::consts::PI;
+
+    // A downlight, symmetric around its axis
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] Synthetic
+[MANUFAC] None
+TILT=NONE
+1 1000 2.0 5 1 1 2 0.1 0.1 0.0
+1.0 1.0 20
+0 22.5 45 67.5 90
+0
+400 360, 200 40
+0";
+
+    #[test]
+    fn ies_profile() {
+        let ies = Ies::parse(DOWNLIGHT).unwrap();
+        assert!((ies.intensity(0.0, 0.0) - 1.0).abs() < 1e-9);
+        assert!((ies.intensity(PI / 8.0, 1.0) - 0.9).abs() < 1e-9);
+        assert!((ies.intensity(PI / 16.0, 3.0) - 0.95).abs() < 1e-9);
+        assert_eq!(ies.intensity(PI * 0.75, 0.0), 0.0);
+
+        assert!(Ies::parse("TILT=NONE\n1 1000").is_err());
+    }
+}
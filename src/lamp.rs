@@ -0,0 +1,180 @@
+use energy::Energy;
+use direction::Direction;
+use vector3::Vector3;
+use microfacet::Frame;
+use sample::luminance;
+use rand::{Rng, XorShiftRng};
+use std::f64::consts::PI;
+
+// Light without geometry, which rays can't hit and the sampler connects to
+// directly. Intensities are in W/sr and irradiance in W/m² per channel.
+#[derive(Debug, Clone)]
+pub enum Lamp {
+    Point {
+        position: Vector3,
+        intensity: Energy,
+    },
+    // Full intensity up to `falloff` from the axis, fading to nothing at `cone`
+    Spot {
+        position: Vector3,
+        axis: Direction,
+        intensity: Energy,
+        cone: f64,
+        falloff: f64,
+    },
+    // Sun or moon, `toward` it from the scene. `angle` is the angular diameter,
+    // zero for perfectly sharp shadows.
+    Distant {
+        toward: Direction,
+        irradiance: Energy,
+        angle: f64,
+    },
+}
+
+impl Lamp {
+    // Radiating `power` watts equally in all directions
+    pub fn point(position: Vector3, power: Energy) -> Lamp {
+        Lamp::Point {
+            position: position,
+            intensity: power.amplified(1.0 / (4.0 * PI)),
+        }
+    }
+
+    // Radiating `power` watts into a cone with half angle `cone` in radians, approximating the falloff
+    pub fn spot(position: Vector3, axis: Direction, power: Energy, cone: f64, falloff: f64) -> Lamp {
+        let solid_angle = 2.0 * PI * (1.0 - ((cone + falloff) / 2.0).cos());
+        Lamp::Spot {
+            position: position,
+            axis: axis.unit(),
+            intensity: power.amplified(1.0 / solid_angle),
+            cone: cone,
+            falloff: falloff.min(cone),
+        }
+    }
+
+    // The sun at noon on a clear day gives around 1000 W/m² with a diameter of 0.0093 radians
+    pub fn sun(toward: Direction, irradiance: Energy, angle: f64) -> Lamp {
+        Lamp::Distant {
+            toward: toward.unit(),
+            irradiance: irradiance,
+            angle: angle,
+        }
+    }
+
+    // Whether the lamp is somewhere in the scene, rather than infinitely far away
+    pub fn local(&self) -> bool {
+        self.position().is_some()
+    }
+
+    pub fn position(&self) -> Option<Vector3> {
+        match *self {
+            Lamp::Point { ref position, .. } | Lamp::Spot { ref position, .. } => Some(position.clone()),
+            Lamp::Distant { .. } => None,
+        }
+    }
+
+    // Emitted luminance of local lamps, for choosing between them
+    pub fn power(&self) -> f64 {
+        match *self {
+            Lamp::Point { ref intensity, .. } => luminance(intensity) * 4.0 * PI,
+            Lamp::Spot { ref intensity, cone, .. } => luminance(intensity) * 2.0 * PI * (1.0 - cone.cos()),
+            Lamp::Distant { .. } => 0.0,
+        }
+    }
+
+    // Samples the light reaching `point`. Returns the direction towards the lamp,
+    // the distance to it and the irradiance on a surface facing it.
+    pub fn sample(&self, point: &Vector3, rng: &mut XorShiftRng) -> (Direction, f64, Energy) {
+        match *self {
+            Lamp::Point { ref position, ref intensity } => {
+                let offset = position - point;
+                let dist = offset.len();
+                (offset.unit(), dist, intensity.amplified(1.0 / (dist * dist)))
+            }
+            Lamp::Spot { ref position, ref axis, ref intensity, cone, falloff } => {
+                let offset = position - point;
+                let dist = offset.len();
+                let to = offset.unit();
+                let cos = -to.dot(axis);
+                let fade = smoothstep(cone.cos(), falloff.cos(), cos);
+                (to, dist, intensity.amplified(fade / (dist * dist)))
+            }
+            Lamp::Distant { ref toward, ref irradiance, angle } => {
+                if angle <= 0.0 {
+                    return (toward.clone(), f64::INFINITY, irradiance.clone());
+                }
+
+                // Uniformly over the disc, each direction carrying an equal share
+                let max = (angle / 2.0).cos();
+                let cos = 1.0 - rng.gen_range::<f64>(0.0, 1.0) * (1.0 - max);
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen_range::<f64>(0.0, 1.0);
+                let to = Frame::new(toward).world(&Direction {
+                    x: sin * phi.cos(),
+                    y: sin * phi.sin(),
+                    z: cos,
+                });
+                (to, f64::INFINITY, irradiance.clone())
+            }
+        }
+    }
+}
+
+fn smoothstep(low: f64, high: f64, x: f64) -> f64 {
+    if low >= high {
+        return if x >= high { 1.0 } else { 0.0 };
+    }
+
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lamp;
+    use material::Lambert;
+    use scene::Scene;
+    use sphere::Sphere;
+    use surface::Surface;
+    use energy::Energy;
+    use vector3::Vector3;
+    use direction::Direction;
+    use rand::{SeedableRng, XorShiftRng};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn lamp_falloff() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 1, 2]);
+        let origin = Vector3 { x: 0.0, y: 0.0, z: 0.0 };
+        let power = Energy { x: 4.0 * PI, y: 4.0 * PI, z: 4.0 * PI };
+
+        let point = Lamp::point(Vector3 { x: 0.0, y: 2.0, z: 0.0 }, power.clone());
+        let (to, dist, e) = point.sample(&origin, &mut rng);
+        assert!(to.y > 0.999 && (dist - 2.0).abs() < 1e-9);
+        assert!((e.x - 0.25).abs() < 1e-9);
+
+        let down = Direction { x: 0.0, y: -1.0, z: 0.0 };
+        let spot = Lamp::spot(Vector3 { x: 0.0, y: 1.0, z: 0.0 }, down.clone(), power, 0.5, 0.3);
+        assert!(spot.sample(&origin, &mut rng).2.x > 0.0);
+        assert_eq!(spot.sample(&Vector3 { x: 1.0, y: 0.0, z: 0.0 }, &mut rng).2.x, 0.0);
+
+        // A wide sun stays within its disc
+        let sun = Lamp::sun(down.invert(), Energy { x: 1000.0, y: 1000.0, z: 1000.0 }, 0.2);
+        for _ in 0..100 {
+            let (to, _, e) = sun.sample(&origin, &mut rng);
+            assert!(to.y >= 0.1f64.cos() - 1e-9 && e.x == 1000.0);
+        }
+    }
+
+    #[test]
+    fn lamp_shadow() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(&lambert))];
+        let scene = Scene::new(&surfaces);
+        let up = Direction { x: 0.0, y: 1.0, z: 0.0 };
+
+        assert!(!scene.visible(&Vector3 { x: 0.0, y: -2.0, z: 0.0 }, &up, 4.0));
+        assert!(scene.visible(&Vector3 { x: 0.0, y: -2.0, z: 0.0 }, &up, 1.0));
+        assert!(scene.visible(&Vector3 { x: 0.0, y: 0.5, z: 0.0 }, &up, 4.0));
+    }
+}
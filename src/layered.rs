@@ -0,0 +1,256 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, absorb, pass, black, white};
+use medium::Medium;
+use microfacet::{Frame, Ggx, reflect, refract, fresnel_dielectric};
+use surface::Geometry;
+use texture::Texture;
+use rand::{Rng, XorShiftRng};
+
+// Bounces between the coat and the base before a path is considered absorbed
+const MAX_INTERNAL: usize = 16;
+
+// A dielectric coat over any other material, for car paint, lacquered wood and
+// varnish. Light refracted into the coat is tinted on its way to the base and
+// back, and may bounce between the coat and the base several times.
+#[derive(Debug)]
+pub struct Coated<'a> {
+    pub base: &'a Material,
+    pub roughness: Texture, // Of the coat surface, zero is smooth
+    pub ior: f64,
+    pub thickness: f64,
+    pub tint: Texture, // Color transmitted through a thickness of one
+}
+
+impl<'a> Coated<'a> {
+    // Clear, smooth varnish
+    pub fn new(base: &'a Material) -> Coated<'a> {
+        Coated {
+            base: base,
+            roughness: Texture::value(0.0),
+            ior: 1.5,
+            thickness: 0.0,
+            tint: Texture::value(1.0),
+        }
+    }
+
+    // Direction inside the coat of light refracted at the mean surface from or
+    // towards `w`, which points away from the surface. The roughness of the coat
+    // only spreads its reflection.
+    fn inside(&self, w: &Direction) -> Direction {
+        let (_, t) = refract(w, &Direction { x: 0.0, y: 0.0, z: 1.0 }, self.ior);
+        t
+    }
+
+    // Transmittance of a crossing of the coat at `cos` to the normal
+    fn crossing(&self, tint: &Energy, cos: f64) -> Energy {
+        if self.thickness == 0.0 {
+            return white();
+        }
+
+        let length = self.thickness / cos.abs().max(1e-3);
+        Energy {
+            x: tint.x.powf(length),
+            y: tint.y.powf(length),
+            z: tint.z.powf(length),
+        }
+    }
+}
+
+impl<'a> Material for Coated<'a> {
+    // Random walk through the layers in the local frame of the coat. Paths crossing
+    // the coat once each way are what `evaluate` covers, reflections off the coat
+    // and paths bouncing inside it are singular.
+    fn sample(&self, g: &Geometry, inc: &Direction, dist: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        if !inc.enters(&g.normal) {
+            return pass(inc);
+        }
+
+        let frame = Frame::new(&g.normal);
+        let tint = self.tint.filtered(g);
+        let wo = frame.local(&inc.invert());
+
+        // The coat reflects or lets the light in
+        if rng.gen_range(0.0, 1.0) < fresnel_dielectric(wo.z, self.ior) {
+            let ggx = Ggx::roughness(self.roughness.filtered_scalar(g), 0.0);
+            let m = ggx.sample(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+            let cos = wo.dot(&m);
+            let wi = reflect(&wo, &m);
+            if cos <= 0.0 || wi.z <= 0.0 {
+                return absorb(inc);
+            }
+            return (true, frame.world(&wi), white().amplified(ggx.g(&wo, &wi) * cos / (wo.z * m.z)), true);
+        }
+
+        let mut down = self.inside(&wo);
+        let mut strength = white();
+
+        for bounce in 0..MAX_INTERNAL {
+            strength = &strength * &self.crossing(&tint, down.z);
+
+            let (ok, out, base, singular) = self.base.sample(g, &frame.world(&down), dist, rng);
+            if !ok {
+                return absorb(inc);
+            }
+            strength = &strength * &base;
+
+            // Transmitted through the base
+            let up = frame.local(&out);
+            if up.z <= 0.0 {
+                return (true, out, strength, true);
+            }
+
+            strength = &strength * &self.crossing(&tint, up.z);
+
+            // Seen from inside, with the normal flipped so the coat faces up
+            let w = Direction { x: -up.x, y: -up.y, z: up.z };
+            if rng.gen_range(0.0, 1.0) >= fresnel_dielectric(w.z, 1.0 / self.ior) {
+                let (_, t) = refract(&w, &Direction { x: 0.0, y: 0.0, z: 1.0 }, 1.0 / self.ior);
+                let exit = Direction { x: t.x, y: t.y, z: -t.z };
+                return (true, frame.world(&exit), strength, singular || bounce > 0);
+            }
+
+            down = Direction { x: up.x, y: up.y, z: -up.z };
+        }
+
+        absorb(inc)
+    }
+
+    // The base seen through the coat. What the coat reflects on the way in and out
+    // is lost, and the light leaving the coat spreads by 1 / ior^2 in solid angle.
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        let frame = Frame::new(&g.normal);
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return black();
+        }
+
+        let tint = self.tint.filtered(g);
+        let (down, up) = (self.inside(&wo), self.inside(&wi).invert());
+        let base = self.base.evaluate(g, &frame.world(&down), &frame.world(&up));
+        let crossings = &self.crossing(&tint, down.z) * &self.crossing(&tint, up.z);
+        let transmitted = (1.0 - fresnel_dielectric(wo.z, self.ior)) * (1.0 - fresnel_dielectric(wi.z, self.ior));
+        (&base * &crossings).amplified(transmitted / (self.ior * self.ior))
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        let frame = Frame::new(&g.normal);
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let (down, up) = (self.inside(&wo), self.inside(&wi).invert());
+        let transmitted = (1.0 - fresnel_dielectric(wo.z, self.ior)) * (1.0 - fresnel_dielectric(wi.z, self.ior));
+        transmitted * self.base.pdf(g, &frame.world(&down), &frame.world(&up)) * wi.z / (self.ior * self.ior * up.z)
+    }
+
+    fn emit(&self, g: &Geometry, dir: &Direction) -> Energy {
+        self.base.emit(g, dir)
+    }
+
+    fn power(&self) -> f64 {
+        self.base.power()
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.base.medium()
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        &self.base.albedo(g) * &self.tint.filtered(g)
+    }
+
+    fn opacity(&self, g: &Geometry) -> f64 {
+        self.base.opacity(g)
+    }
+
+    fn masked(&self) -> bool {
+        self.base.masked()
+    }
+
+    fn transparent(&self) -> bool {
+        self.base.transparent()
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Coated")
+            .with(self.base.digest())
+            .with(self.roughness.digest())
+            .values(&[self.ior, self.thickness])
+            .with(self.tint.digest())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coated;
+    use conductor::Conductor;
+    use material::{Material, Lambert, white};
+    use surface::Geometry;
+    use texture::Texture;
+    use direction::Direction;
+    use furnace;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::f64::consts::PI;
+
+    fn albedo(m: &Material) -> f64 {
+        let g = Geometry::new(&Direction { x: 0.0, y: 1.0, z: 0.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.6, y: -0.8, z: 0.0 };
+        furnace::albedo(m, &g, &inc, 50000, &mut XorShiftRng::from_seed([9, 8, 7, 6])).average()
+    }
+
+    #[test]
+    fn coated_absorbs_in_coat() {
+        let silver = Conductor::silver(0.2);
+        let mut coated = Coated::new(&silver);
+        coated.roughness = Texture::value(0.3);
+
+        let clear = albedo(&coated);
+        assert!(clear < 1.0 && clear > 0.8, "albedo {}", clear);
+
+        coated.thickness = 0.5;
+        coated.tint = Texture::constant(0.2, 0.5, 0.9);
+        let tinted = albedo(&coated);
+        assert!(tinted < clear * 0.8, "albedo {}", tinted);
+    }
+
+    #[test]
+    fn coated_base_evaluated() {
+        // Paths through the coat once each way are weighted by what direct lighting
+        // finds for them, the rest is singular
+        let lambert = Lambert::new(0.8, 0.5, 0.2);
+        let mut coated = Coated::new(&lambert);
+        coated.roughness = Texture::value(0.3);
+        coated.thickness = 0.2;
+        coated.tint = Texture::constant(0.9, 0.8, 0.7);
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.6, y: 0.0, z: -0.8 };
+        let mut rng = XorShiftRng::from_seed([2, 7, 1, 8]);
+        let mut through = 0;
+
+        for _ in 0..1000 {
+            let (ok, out, weight, singular) = coated.sample(&g, &inc, 1.0, &mut rng);
+            if !ok || singular {
+                continue;
+            }
+            let expected = coated.evaluate(&g, &inc, &out).amplified(out.z / coated.pdf(&g, &inc, &out));
+            assert!((&weight - &expected).len() < 1e-9, "{:?} {:?}", weight, expected);
+            through += 1;
+        }
+        assert!(through > 200 && through < 900, "through {}", through);
+
+        // The pdf integrates to the share of those paths
+        let n = 20000;
+        let covered = furnace::mean(n, &mut rng, |rng| {
+            let z: f64 = rng.gen_range(0.0, 1.0);
+            let phi = rng.gen_range(0.0, 2.0 * PI);
+            let r = (1.0 - z * z).sqrt();
+            let out = Direction { x: r * phi.cos(), y: r * phi.sin(), z: z };
+            white().amplified(coated.pdf(&g, &inc, &out) * 2.0 * PI)
+        });
+        assert!((covered.x - through as f64 / 1000.0).abs() < 0.05, "{} {}", covered.x, through);
+    }
+}
@@ -1,22 +1,59 @@
+// Trait objects and struct literals are written in the style of Rust 2015
+#![allow(bare_trait_objects, clippy::redundant_field_names)]
+
 extern crate rand;
 extern crate image;
 extern crate cgmath;
 extern crate num;
+extern crate exr;
 
 pub mod camera;
-// pub mod constants;
-// pub mod direction;
-// pub mod energy;
-// pub mod material;
-// pub mod matrix4;
+pub mod checkpoint;
+pub mod conductor;
+pub mod constants;
+pub mod denoise;
+pub mod differential;
+pub mod direction;
+pub mod distributed;
+pub mod distribution;
+pub mod encode;
+pub mod energy;
+pub mod environment;
+pub mod fog;
+pub mod grid;
+pub mod ies;
+pub mod lamp;
+pub mod layered;
+pub mod lighttree;
+pub mod mapping;
+pub mod mask;
+pub mod material;
+pub mod matrix4;
+pub mod medium;
+pub mod merl;
+pub mod microfacet;
+pub mod openexr;
+pub mod preview;
+pub mod principled;
+pub mod progressive;
+pub mod quad;
 pub mod ray;
-// pub mod renderer;
-// pub mod sampler;
-// pub mod scene;
-// pub mod sphere;
-// pub mod surface;
-// pub mod vector3;
-// pub mod sample;
+pub mod ray3;
+pub mod renderer;
+pub mod sampler;
+pub mod scene;
+pub mod sheen;
+pub mod spectrum;
+pub mod sphere;
+pub mod subsurface;
+pub mod surface;
+pub mod texture;
+pub mod thinfilm;
+pub mod vector3;
+pub mod sample;
+
+#[cfg(test)]
+mod furnace;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,494 @@
+use lamp::Lamp;
+use scene::Scene;
+use direction::Direction;
+use vector3::Vector3;
+use distribution::Distribution;
+use std::collections::HashMap;
+use std::f64::consts::{PI, FRAC_PI_2};
+
+// A light the sampler can connect a shading point to, a lamp or an emissive
+// surface named by its index in the scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Emitter {
+    Lamp(usize),
+    Surface(usize),
+}
+
+// How the sampler picks the lights it connects a shading point to. Distant lamps
+// are always connected, the strategies choose one of the local lamps and
+// emissive surfaces. With `All` every lamp is connected and emissive surfaces
+// are only found by hitting them.
+#[derive(Debug, Clone)]
+pub enum Selection {
+    All,
+    Uniform(Lights),
+    Power(Lights, Distribution), // Proportional to the power of each light
+    Tree(LightTree),
+}
+
+// The lights a strategy chooses from, and where each is in the list
+#[derive(Debug, Clone)]
+pub struct Lights {
+    pub emitters: Vec<Emitter>,
+    index: HashMap<Emitter, usize>,
+}
+
+impl Selection {
+    pub fn uniform(scene: &Scene) -> Selection {
+        Selection::Uniform(Lights::new(sources(scene).iter().map(|s| s.emitter).collect()))
+    }
+
+    pub fn power(scene: &Scene) -> Selection {
+        let sources = sources(scene);
+        let distribution = Distribution::new(sources.iter().map(|s| s.power).collect());
+        Selection::Power(Lights::new(sources.iter().map(|s| s.emitter).collect()), distribution)
+    }
+
+    pub fn tree(scene: &Scene) -> Selection {
+        Selection::Tree(LightTree::new(&sources(scene)))
+    }
+
+    // Picks a light for `point` with `u` in [0, 1). Returns it and the chance
+    // of picking it, never for `All`.
+    pub fn choose(&self, point: &Vector3, u: f64) -> Option<(Emitter, f64)> {
+        match *self {
+            Selection::All => None,
+            Selection::Uniform(ref lights) => {
+                let n = lights.emitters.len();
+                if n == 0 {
+                    return None;
+                }
+                let i = ((u * n as f64) as usize).min(n - 1);
+                Some((lights.emitters[i], 1.0 / n as f64))
+            }
+            Selection::Power(ref lights, ref distribution) => {
+                if distribution.integral <= 0.0 {
+                    return None;
+                }
+                let (_, pdf, i) = distribution.sample(u);
+                Some((lights.emitters[i], pdf / distribution.count() as f64))
+            }
+            Selection::Tree(ref tree) => tree.choose(point, u),
+        }
+    }
+
+    // The chance of `choose` picking `emitter` for `point`, for weighting the
+    // light found by hitting an emissive surface
+    pub fn chance(&self, point: &Vector3, emitter: Emitter) -> f64 {
+        match *self {
+            Selection::All => 0.0,
+            Selection::Uniform(ref lights) => {
+                if lights.index.contains_key(&emitter) {
+                    1.0 / lights.emitters.len() as f64
+                } else {
+                    0.0
+                }
+            }
+            Selection::Power(ref lights, ref distribution) => {
+                let n = distribution.count() as f64;
+                match lights.index.get(&emitter) {
+                    Some(&i) if distribution.integral > 0.0 => distribution.pdf((i as f64 + 0.5) / n) / n,
+                    _ => 0.0,
+                }
+            }
+            Selection::Tree(ref tree) => tree.chance(point, emitter),
+        }
+    }
+}
+
+impl Lights {
+    fn new(emitters: Vec<Emitter>) -> Lights {
+        Lights {
+            index: emitters.iter().enumerate().map(|(i, &e)| (e, i)).collect(),
+            emitters: emitters,
+        }
+    }
+}
+
+// What the strategies know about a light
+#[derive(Debug)]
+struct Source {
+    emitter: Emitter,
+    min: Vector3,
+    max: Vector3,
+    power: f64,
+    cone: Cone,
+}
+
+// The local lamps and the surfaces of emitting materials
+fn sources(scene: &Scene) -> Vec<Source> {
+    let mut sources = Vec::new();
+
+    for (i, lamp) in scene.lamps.iter().enumerate() {
+        if let Some(position) = lamp.position() {
+            sources.push(Source {
+                emitter: Emitter::Lamp(i),
+                min: position.clone(),
+                max: position,
+                power: lamp.power(),
+                cone: Cone::of(lamp),
+            });
+        }
+    }
+
+    for (i, surface) in scene.surfaces().iter().enumerate() {
+        let power = surface.material().power();
+        if power <= 0.0 {
+            continue;
+        }
+
+        let (min, max) = surface.bounds();
+        sources.push(Source {
+            emitter: Emitter::Surface(i),
+            min: min,
+            max: max,
+            power: power * surface.area(),
+            cone: match surface.facing() {
+                Some(normal) => Cone { axis: normal, spread: 0.0, reach: FRAC_PI_2 },
+                None => Cone { axis: Direction { x: 0.0, y: 0.0, z: 1.0 }, spread: PI, reach: FRAC_PI_2 },
+            },
+        });
+    }
+
+    sources
+}
+
+// Bounding volume hierarchy over the lights (Conty Estevez and Kulla 2018).
+// Every node bounds the position, power and emission directions of its lights,
+// which gives an estimate of their contribution at a point to descend by.
+#[derive(Debug, Clone)]
+pub struct LightTree {
+    nodes: Vec<Node>,
+    leaves: HashMap<Emitter, usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    min: Vector3,
+    max: Vector3,
+    center: Vector3,
+    radius: f64, // Of the sphere around the bounds
+    power: f64,
+    cone: Cone,
+    parent: Option<usize>,
+    content: Content,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Light(Emitter),
+    Children(usize, usize),
+}
+
+// Emission directions within `spread` of `axis`, each emitting up to `reach` away from its direction
+#[derive(Debug, Clone)]
+struct Cone {
+    axis: Direction,
+    spread: f64,
+    reach: f64,
+}
+
+impl LightTree {
+    fn new(sources: &[Source]) -> LightTree {
+        let mut tree = LightTree {
+            nodes: Vec::new(),
+            leaves: HashMap::new(),
+        };
+        let mut indices: Vec<usize> = (0..sources.len()).collect();
+        if !indices.is_empty() {
+            tree.build(sources, &mut indices);
+        }
+        tree
+    }
+
+    // Adds the node over `indices` and returns its position, children before parents
+    fn build(&mut self, sources: &[Source], indices: &mut [usize]) -> usize {
+        if indices.len() == 1 {
+            let source = &sources[indices[0]];
+            let center = (&source.min + &source.max).amplified(0.5);
+            self.nodes.push(Node {
+                radius: (&source.max - &center).len(),
+                center: center,
+                min: source.min.clone(),
+                max: source.max.clone(),
+                power: source.power,
+                cone: source.cone.clone(),
+                parent: None,
+                content: Content::Light(source.emitter),
+            });
+            self.leaves.insert(source.emitter, self.nodes.len() - 1);
+            return self.nodes.len() - 1;
+        }
+
+        // Median split along the widest extent of the centers
+        let centers: Vec<Vector3> = sources.iter().map(|s| (&s.min + &s.max).amplified(0.5)).collect();
+        let (min, max) = bounds(indices.iter().map(|&i| centers[i].clone()));
+        let extent = &max - &min;
+        let key = |i: &usize| {
+            let p = &centers[*i];
+            if extent.x >= extent.y && extent.x >= extent.z {
+                p.x
+            } else if extent.y >= extent.z {
+                p.y
+            } else {
+                p.z
+            }
+        };
+        indices.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+
+        let half = indices.len() / 2;
+        let (left, right) = indices.split_at_mut(half);
+        let (l, r) = (self.build(sources, left), self.build(sources, right));
+        let (min, max) = bounds(vec![self.nodes[l].min.clone(), self.nodes[l].max.clone(), self.nodes[r].min.clone(), self.nodes[r].max.clone()].into_iter());
+
+        let center = (&min + &max).amplified(0.5);
+        self.nodes.push(Node {
+            radius: (&max - &center).len(),
+            center: center,
+            min: min,
+            max: max,
+            power: self.nodes[l].power + self.nodes[r].power,
+            cone: self.nodes[l].cone.union(&self.nodes[r].cone),
+            parent: None,
+            content: Content::Children(l, r),
+        });
+        let node = self.nodes.len() - 1;
+        self.nodes[l].parent = Some(node);
+        self.nodes[r].parent = Some(node);
+        node
+    }
+
+    // Descends from the root choosing children by their estimated contribution
+    pub fn choose(&self, point: &Vector3, mut u: f64) -> Option<(Emitter, f64)> {
+        let mut node = match self.nodes.len() {
+            0 => return None,
+            n => n - 1,
+        };
+        let mut chance = 1.0;
+
+        loop {
+            match self.nodes[node].content {
+                Content::Light(e) => return Some((e, chance)),
+                Content::Children(l, r) => {
+                    let (a, b) = (self.nodes[l].importance(point), self.nodes[r].importance(point));
+                    if a + b <= 0.0 {
+                        return None;
+                    }
+
+                    let p = a / (a + b);
+                    if u < p {
+                        u /= p;
+                        chance *= p;
+                        node = l;
+                    } else {
+                        u = ((u - p) / (1.0 - p)).min(1.0 - 1e-12);
+                        chance *= 1.0 - p;
+                        node = r;
+                    }
+                }
+            }
+        }
+    }
+
+    // Climbs from the leaf of `emitter` multiplying the chances of the choices on the way
+    pub fn chance(&self, point: &Vector3, emitter: Emitter) -> f64 {
+        let mut node = match self.leaves.get(&emitter) {
+            Some(&node) => node,
+            None => return 0.0,
+        };
+        let mut chance = 1.0;
+
+        while let Some(parent) = self.nodes[node].parent {
+            if let Content::Children(l, r) = self.nodes[parent].content {
+                let (a, b) = (self.nodes[l].importance(point), self.nodes[r].importance(point));
+                if a + b <= 0.0 {
+                    return 0.0;
+                }
+
+                let p = a / (a + b);
+                chance *= if node == l { p } else { 1.0 - p };
+            }
+            node = parent;
+        }
+
+        chance
+    }
+}
+
+impl Node {
+    // Upper bound of the intensity towards `point`, over a distance that
+    // doesn't shrink below the size of the node
+    fn importance(&self, point: &Vector3) -> f64 {
+        let offset = point - &self.center;
+        let dist = offset.len();
+        let radius = self.radius;
+
+        let orientation = if self.cone.spread >= PI || dist <= radius {
+            1.0
+        } else {
+            let angle = (self.cone.axis.dot(&offset) / dist).clamp(-1.0, 1.0).acos();
+            let bound = (radius / dist).asin();
+            let rest = (angle - self.cone.spread - bound).max(0.0);
+            if rest >= self.cone.reach { 0.0 } else { rest.cos() }
+        };
+
+        self.power * orientation / (dist * dist).max(radius * radius).max(1e-12)
+    }
+}
+
+impl Cone {
+    fn of(lamp: &Lamp) -> Cone {
+        match *lamp {
+            Lamp::Spot { ref axis, cone, .. } => Cone { axis: axis.clone(), spread: 0.0, reach: cone },
+            _ => Cone { axis: Direction { x: 0.0, y: 0.0, z: 1.0 }, spread: PI, reach: FRAC_PI_2 },
+        }
+    }
+
+    fn union(&self, other: &Cone) -> Cone {
+        let (a, b) = if self.spread >= other.spread { (self, other) } else { (other, self) };
+        let reach = a.reach.max(b.reach);
+        let between = a.axis.dot(&b.axis).clamp(-1.0, 1.0).acos();
+        if (between + b.spread).min(PI) <= a.spread {
+            return Cone { axis: a.axis.clone(), spread: a.spread, reach: reach };
+        }
+
+        let spread = (a.spread + between + b.spread) / 2.0;
+        if spread >= PI || between <= 0.0 {
+            return Cone { axis: a.axis.clone(), spread: PI, reach: reach };
+        }
+
+        // Turns the axis of `a` towards `b` to the middle of the union
+        let turn = spread - a.spread;
+        let axis = (&a.axis.amplified((between - turn).sin()) + &b.axis.amplified(turn.sin())).amplified(1.0 / between.sin());
+        Cone { axis: axis.unit(), spread: spread, reach: reach }
+    }
+}
+
+fn bounds<I: Iterator<Item = Vector3>>(points: I) -> (Vector3, Vector3) {
+    let inf = f64::INFINITY;
+    let mut min = Vector3 { x: inf, y: inf, z: inf };
+    let mut max = Vector3 { x: -inf, y: -inf, z: -inf };
+    for p in points {
+        min = Vector3 { x: min.x.min(p.x), y: min.y.min(p.y), z: min.z.min(p.z) };
+        max = Vector3 { x: max.x.max(p.x), y: max.y.max(p.y), z: max.z.max(p.z) };
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Selection, Emitter};
+    use lamp::Lamp;
+    use scene::Scene;
+    use sphere::Sphere;
+    use quad::Quad;
+    use surface::Surface;
+    use material::Light;
+    use matrix4::Matrix4;
+    use energy::Energy;
+    use vector3::Vector3;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // Light from `emitter` reaching `point`, on a surface facing it
+    fn light(scene: &Scene, emitter: Emitter, point: &Vector3, rng: &mut XorShiftRng) -> f64 {
+        match emitter {
+            Emitter::Lamp(i) => scene.lamps[i].sample(point, rng).2.x,
+            Emitter::Surface(i) => {
+                let surface = &scene.surfaces()[i];
+                let (target, density) = surface.sample(rng);
+                let offset = &target - point;
+                let dist = offset.len();
+                let to = offset.unit();
+                let (g, mat) = surface.at(&target);
+                mat.emit(&g, &to).x * g.geometric.dot(&to).abs() / (density * dist * dist)
+            }
+        }
+    }
+
+    // Relative variance of the light reaching `point` from one chosen emitter
+    fn variance(selection: &Selection, scene: &Scene, point: &Vector3, rng: &mut XorShiftRng) -> f64 {
+        let n = 20000;
+        let (mut sum, mut square) = (0.0, 0.0);
+        for _ in 0..n {
+            let estimate = match selection.choose(point, rng.gen_range(0.0, 1.0)) {
+                Some((e, chance)) => light(scene, e, point, rng) / chance,
+                None => 0.0,
+            };
+            sum += estimate;
+            square += estimate * estimate;
+        }
+
+        let mean = sum / n as f64;
+        (square / n as f64 - mean * mean) / (mean * mean)
+    }
+
+    #[test]
+    fn light_tree_variance() {
+        // A city at night: a grid of glowing street lights and a few bright
+        // signs, seen from the pavement under some of them
+        let street = Light::new(6.0, 6.0, 6.0);
+        let mut surfaces: Vec<Box<Surface>> = Vec::new();
+        let mut signs = Vec::new();
+        for i in 0..64 {
+            for j in 0..64 {
+                let (x, z) = (i as f64 * 100.0, j as f64 * 100.0);
+                if (i * 64 + j) % 97 == 0 {
+                    signs.push(Lamp::point(Vector3 { x: x, y: 4.0, z: z }, Energy { x: 400.0, y: 400.0, z: 400.0 }));
+                } else {
+                    surfaces.push(Box::new(Sphere::new(&street).placed(Matrix4::translation(x, 4.0, z))));
+                }
+            }
+        }
+        let mut scene = Scene::new(&surfaces);
+        scene.lamps = signs;
+
+        let strategies = [Selection::uniform(&scene), Selection::power(&scene), Selection::tree(&scene)];
+        let mut rng = XorShiftRng::from_seed([4, 0, 9, 6]);
+        let mut variances = [0.0; 3];
+        for k in 0..8 {
+            let point = Vector3 { x: (5 * k + 3) as f64 * 100.0 + 3.0, y: 0.0, z: (60 - 7 * k) as f64 * 100.0 - 2.0 };
+            for (s, strategy) in strategies.iter().enumerate() {
+                variances[s] += variance(strategy, &scene, &point, &mut rng);
+            }
+        }
+        assert!(variances[2] < variances[0] / 10.0 && variances[2] < variances[1] / 10.0, "{:?}", variances);
+
+        // A pick visits one node on each level of the tree over the 4096 lights
+        if let Selection::Tree(ref tree) = strategies[2] {
+            let visited = tree.leaves.values().map(|&leaf| {
+                let (mut node, mut n) = (leaf, 1);
+                while let Some(parent) = tree.nodes[node].parent {
+                    node = parent;
+                    n += 1;
+                }
+                n
+            });
+            assert_eq!(Some(13), visited.max());
+        }
+    }
+
+    #[test]
+    fn light_tree_chance() {
+        let light = Light::new(1.0, 2.0, 3.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(&light).placed(Matrix4::translation(2.0, 0.0, 0.0))),
+                                               Box::new(Quad::new(&light).placed(Matrix4::translation(-1.0, 1.0, 3.0))),
+                                               Box::new(Quad::new(&light).placed(Matrix4::translation(0.0, -2.0, -1.0)))];
+        let mut scene = Scene::new(&surfaces);
+        scene.lamps = vec![Lamp::point(Vector3 { x: 0.0, y: 3.0, z: 0.0 }, Energy { x: 5.0, y: 5.0, z: 5.0 }),
+                           Lamp::sun(Vector3 { x: 0.0, y: 1.0, z: 0.0 }, Energy { x: 5.0, y: 5.0, z: 5.0 }, 0.0)];
+        let emitters = [Emitter::Lamp(0), Emitter::Surface(0), Emitter::Surface(1), Emitter::Surface(2)];
+        let mut rng = XorShiftRng::from_seed([1, 9, 8, 4]);
+
+        for selection in &[Selection::uniform(&scene), Selection::power(&scene), Selection::tree(&scene)] {
+            assert_eq!(0.0, selection.chance(&Vector3 { x: 0.0, y: 0.0, z: 0.0 }, Emitter::Lamp(1)));
+            for _ in 0..20 {
+                let point = Vector3 { x: rng.gen_range(-4.0, 4.0), y: rng.gen_range(-4.0, 4.0), z: rng.gen_range(-4.0, 4.0) };
+                let total: f64 = emitters.iter().map(|&e| selection.chance(&point, e)).sum();
+                assert!((total - 1.0).abs() < 1e-9, "{:?} {}", selection, total);
+
+                let (e, chance) = selection.choose(&point, rng.gen_range(0.0, 1.0)).unwrap();
+                assert!((chance - selection.chance(&point, e)).abs() < 1e-9);
+            }
+        }
+    }
+}
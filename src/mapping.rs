@@ -0,0 +1,171 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, absorb, black};
+use medium::Medium;
+use surface::Geometry;
+use texture::Texture;
+use rand::XorShiftRng;
+
+#[derive(Debug)]
+pub enum Mapping {
+    // Tangent space normals with the channels mapped from 0-1 to -1-1, blue along the normal
+    Normal(Texture),
+    // Heights in the first channel, the slopes tilt the normal by `strength`
+    Bump { height: Texture, strength: f64 },
+}
+
+// Shades `material` with a normal tilted by a normal or bump map
+#[derive(Debug)]
+pub struct Mapped<'a> {
+    pub material: &'a Material,
+    pub mapping: Mapping,
+}
+
+impl<'a> Mapped<'a> {
+    pub fn new(material: &'a Material, mapping: Mapping) -> Mapped<'a> {
+        Mapped {
+            material: material,
+            mapping: mapping,
+        }
+    }
+
+    // Falls back to the unmapped geometry when the viewer sees the back of the tilted
+    // normal, which would otherwise shade a surface facing away
+    fn shade(&self, g: &Geometry, inc: &Direction) -> Geometry {
+        let normal = match self.mapping {
+            Mapping::Normal(ref map) => {
+                let n = map.filtered(g);
+                &(&(&g.tangent * (2.0 * n.x - 1.0)) + &(&g.bitangent * (2.0 * n.y - 1.0))) + &(&g.normal * (2.0 * n.z - 1.0))
+            }
+            Mapping::Bump { ref height, strength } => {
+                // Slopes over the pixel footprint, so distant bumps flatten instead of aliasing
+                let (du, dv) = height.step();
+                let (du, dv) = (du.max(g.footprint), dv.max(g.footprint));
+                let h = height.lookup(g.u, g.v, g.footprint).x;
+                let slope_u = (height.lookup(g.u + du, g.v, g.footprint).x - h) / du;
+                let slope_v = (height.lookup(g.u, g.v + dv, g.footprint).x - h) / dv;
+                &g.normal - &(&(&g.tangent * (strength * slope_u)) + &(&g.bitangent * (strength * slope_v)))
+            }
+        };
+
+        let shaded = g.shaded(&normal.unit());
+        if shaded.consistent(inc) { shaded } else { g.clone() }
+    }
+}
+
+impl<'a> Material for Mapped<'a> {
+    // Directions on the other side of the geometric surface than the tilted normal
+    // suggests would leak light through it, those paths are dropped
+    fn sample(&self, g: &Geometry, inc: &Direction, dist: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        let shaded = self.shade(g, inc);
+        let (ok, out, strength, singular) = self.material.sample(&shaded, inc, dist, rng);
+
+        if ok && !shaded.consistent(&out) {
+            return absorb(inc);
+        }
+
+        (ok, out, strength, singular)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        let shaded = self.shade(g, inc);
+        if !shaded.consistent(out) {
+            return black();
+        }
+
+        self.material.evaluate(&shaded, inc, out)
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        let shaded = self.shade(g, inc);
+        if !shaded.consistent(out) {
+            return 0.0;
+        }
+
+        self.material.pdf(&shaded, inc, out)
+    }
+
+    fn emit(&self, g: &Geometry, dir: &Direction) -> Energy {
+        self.material.emit(&self.shade(g, dir), dir)
+    }
+
+    fn power(&self) -> f64 {
+        self.material.power()
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.material.medium()
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.material.albedo(g)
+    }
+
+    fn opacity(&self, g: &Geometry) -> f64 {
+        self.material.opacity(g)
+    }
+
+    fn masked(&self) -> bool {
+        self.material.masked()
+    }
+
+    fn transparent(&self) -> bool {
+        self.material.transparent()
+    }
+
+    fn digest(&self) -> u64 {
+        let mapping = match self.mapping {
+            Mapping::Normal(ref map) => Digest::new("Normal").with(map.digest()),
+            Mapping::Bump { ref height, strength } => Digest::new("Bump").with(height.digest()).value(strength),
+        };
+        Digest::new("Mapped").with(self.material.digest()).with(mapping.finish()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mapped, Mapping};
+    use material::{Material, Lambert};
+    use surface::Geometry;
+    use texture::{Texture, ImageTexture, Wrap};
+    use direction::Direction;
+    use rand::{SeedableRng, XorShiftRng};
+
+    #[test]
+    fn mapping_tilts_normal() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.5, 0.5);
+        let inc = Direction { x: 0.0, y: 0.0, z: -1.0 };
+
+        let flat = Mapped::new(&lambert, Mapping::Normal(Texture::constant(0.5, 0.5, 1.0)));
+        assert!((flat.shade(&g, &inc).normal.z - 1.0).abs() < 1e-9);
+
+        let tilted = Mapped::new(&lambert, Mapping::Normal(Texture::constant(1.0, 0.5, 1.0)));
+        let n = tilted.shade(&g, &inc).normal;
+        assert!((n.dot(&g.tangent) - 0.5f64.sqrt()).abs() < 1e-9);
+
+        // Rising along u, the normal leans back against the tangent
+        let black = Direction { x: 0.0, y: 0.0, z: 0.0 };
+        let white = Direction { x: 1.0, y: 1.0, z: 1.0 };
+        let ramp = Texture::Image(ImageTexture::new(2, 1, vec![black, white], Wrap::Clamp));
+        let bumped = Mapped::new(&lambert, Mapping::Bump { height: ramp, strength: 1.0 });
+        let n = bumped.shade(&g, &inc).normal;
+        assert!((n.dot(&g.tangent) + 0.5f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mapping_does_not_leak() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.5, 0.5);
+        let inc = Direction { x: 0.6, y: 0.0, z: -0.8 };
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        // Strongly tilted away from the incoming ray
+        let m = Mapped::new(&lambert, Mapping::Normal(Texture::constant(0.05, 0.5, 0.6)));
+        for _ in 0..1000 {
+            let (ok, out, _, _) = m.sample(&g, &inc, 0.0, &mut rng);
+            assert!(!ok || out.dot(&g.geometric) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::Material;
+use medium::Medium;
+use surface::Geometry;
+use texture::Texture;
+use rand::XorShiftRng;
+
+// Cuts holes into `material` where the first channel of `opacity` is below one,
+// for leaves and fences. With a `cutoff` the surface is either fully there or cut
+// out, otherwise rays pass with a chance of the transparency. Two-sided surfaces
+// shade the back like the front, for thin planar surfaces.
+#[derive(Debug)]
+pub struct Masked<'a> {
+    pub material: &'a Material,
+    pub opacity: Texture,
+    pub cutoff: Option<f64>,
+    pub two_sided: bool,
+}
+
+impl<'a> Masked<'a> {
+    pub fn new(material: &'a Material, opacity: Texture) -> Masked<'a> {
+        Masked {
+            material: material,
+            opacity: opacity,
+            cutoff: None,
+            two_sided: false,
+        }
+    }
+
+    // Opaque but shaded from both sides
+    pub fn two_sided(material: &'a Material) -> Masked<'a> {
+        Masked {
+            material: material,
+            opacity: Texture::value(1.0),
+            cutoff: None,
+            two_sided: true,
+        }
+    }
+
+    // Faces the geometry towards `inc` when the back of a two-sided surface is seen
+    fn facing(&self, g: &Geometry, inc: &Direction) -> Geometry {
+        if !self.two_sided || inc.enters(&g.geometric) {
+            return g.clone();
+        }
+
+        let mut flipped = g.clone();
+        flipped.normal = g.normal.invert();
+        flipped.geometric = g.geometric.invert();
+        flipped.bitangent = g.bitangent.invert();
+        flipped
+    }
+}
+
+impl<'a> Material for Masked<'a> {
+    fn sample(&self, g: &Geometry, inc: &Direction, dist: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        self.material.sample(&self.facing(g, inc), inc, dist, rng)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        self.material.evaluate(&self.facing(g, inc), inc, out)
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        self.material.pdf(&self.facing(g, inc), inc, out)
+    }
+
+    fn emit(&self, g: &Geometry, dir: &Direction) -> Energy {
+        self.material.emit(&self.facing(g, dir), dir)
+    }
+
+    fn power(&self) -> f64 {
+        self.material.power()
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.material.medium()
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.material.albedo(g)
+    }
+
+    fn opacity(&self, g: &Geometry) -> f64 {
+        let opacity = self.opacity.filtered_scalar(g) * self.material.opacity(g);
+        match self.cutoff {
+            Some(cutoff) if opacity >= cutoff => 1.0,
+            Some(_) => 0.0,
+            None => opacity,
+        }
+    }
+
+    fn masked(&self) -> bool {
+        true
+    }
+
+    fn transparent(&self) -> bool {
+        self.material.transparent()
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Masked")
+            .with(self.material.digest())
+            .with(self.opacity.digest())
+            .values(&[self.cutoff.unwrap_or(-1.0), self.two_sided as u8 as f64])
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Masked;
+    use material::{Material, Lambert};
+    use layered::Coated;
+    use sheen::Sheen;
+    use quad::Quad;
+    use scene::Scene;
+    use surface::Surface;
+    use texture::Texture;
+    use energy::Energy;
+    use ray3::Ray3;
+    use vector3::Vector3;
+    use direction::Direction;
+    use rand::{SeedableRng, XorShiftRng};
+
+    fn ray(x: f64, y: f64, z: f64) -> Ray3 {
+        Ray3 {
+            origin: Vector3 { x: x, y: y, z: z },
+            direction: Direction { x: 0.0, y: 0.0, z: -z.signum() },
+        }
+    }
+
+    #[test]
+    fn masked_cutout() {
+        // Opaque in the lower left quarter
+        let lambert = Lambert::new(0.5, 0.5, 0.5);
+        let mut leaf = Masked::new(&lambert, Texture::Checker {
+            scale: 2.0,
+            even: Energy { x: 1.0, y: 1.0, z: 1.0 },
+            odd: Energy { x: 0.0, y: 0.0, z: 0.0 },
+        });
+        leaf.cutoff = Some(0.5);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(&leaf))];
+        let scene = Scene::new(&surfaces);
+        assert!(scene.intersect(&ray(-0.25, -0.25, 1.0)).is_some());
+        assert!(scene.intersect(&ray(0.25, -0.25, 1.0)).is_none());
+
+        // Half the rays pass a half transparent surface
+        let veil = Masked::new(&lambert, Texture::value(0.5));
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(&veil))];
+        let scene = Scene::new(&surfaces);
+        let hits = (0..1000).filter(|&i| scene.intersect(&ray(i as f64 / 1000.0 - 0.5, 0.1, 1.0)).is_some()).count();
+        assert!(hits > 400 && hits < 600, "hits {}", hits);
+
+        // Wrappers keep the cutout
+        let coated = Coated::new(&leaf);
+        let sheen = Sheen::over(&leaf, 0.5, 0.5, 0.5, 0.5);
+        let wrapped: [&Material; 2] = [&coated, &sheen];
+        for m in wrapped.iter() {
+            assert!(m.masked());
+            let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(*m))];
+            let scene = Scene::new(&surfaces);
+            assert!(scene.intersect(&ray(-0.25, -0.25, 1.0)).is_some());
+            assert!(scene.intersect(&ray(0.25, -0.25, 1.0)).is_none());
+        }
+    }
+
+    #[test]
+    fn masked_two_sided() {
+        let lambert = Lambert::new(0.5, 0.5, 0.5);
+        let quad = Quad::new(&lambert);
+        let inc = Direction { x: 0.0, y: 0.0, z: 1.0 };
+        let mut rng = XorShiftRng::from_seed([6, 6, 6, 1]);
+
+        // Seen from behind, a one-sided surface lets the ray through
+        let (g, _) = quad.at(&Vector3 { x: 0.0, y: 0.0, z: 0.0 });
+        let (_, out, _, _) = lambert.sample(&g, &inc, 1.0, &mut rng);
+        assert!(out.z > 0.0);
+
+        let sheet = Masked::two_sided(&lambert);
+        for _ in 0..100 {
+            let (ok, out, _, _) = sheet.sample(&g, &inc, 1.0, &mut rng);
+            assert!(ok && out.z < 0.0);
+            assert!(sheet.pdf(&g, &inc, &out) > 0.0);
+        }
+    }
+}
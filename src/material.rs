@@ -1,255 +1,487 @@
 use energy::Energy;
+use encode::Digest;
 use direction::Direction;
-use rand::{Rng, ThreadRng};
+use surface::Geometry;
+use texture::Texture;
+use medium::Medium;
+use thinfilm::ThinFilm;
+use ies::Ies;
+use sample::luminance;
+use rand::{Rng, XorShiftRng};
 use std::f64::consts::PI;
+use std::fmt::Debug;
+
+// Interaction of light with a surface. `inc` is the direction of the incoming
+// ray, `out` the direction the path continues in, both leaving from the point
+// described by `g`. Implement this to add materials outside the crate.
+pub trait Material: Debug {
+    // Samples the direction the path continues in. Returns false if the path is
+    // absorbed, otherwise the direction and the strength the signal is scaled by,
+    // and whether the direction came from a singular lobe `evaluate` and `pdf` leave out.
+    // `dist` is the distance travelled by `inc`, used for absorption inside the material.
+    fn sample(&self, g: &Geometry, inc: &Direction, dist: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool);
+
+    // The bsdf for light travelling from `out` back along `inc`. Lobes with a
+    // singular or cone shaped distribution (mirror, glossy, refraction) evaluate to zero.
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy;
+
+    // Solid angle density of `sample` choosing `out` through the lobes `evaluate` covers
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64;
+
+    fn emit(&self, _: &Geometry, _: &Direction) -> Energy {
+        Energy { x: 0.0, y: 0.0, z: 0.0 }
+    }
+
+    // Rough luminance leaving a unit of area, for choosing between emissive
+    // surfaces. Surfaces of materials without it are only lit by hitting them.
+    fn power(&self) -> f64 {
+        0.0
+    }
+
+    // The medium filling the inside of surfaces of this material, which the
+    // sampler scatters rays through until they reach the surface again
+    fn medium(&self) -> Option<&Medium> {
+        None
+    }
+
+    // Surface color as seen by the denoiser, independent of lighting
+    fn albedo(&self, g: &Geometry) -> Energy;
+
+    // Chance of a ray hitting the surface instead of passing through a cutout,
+    // checked by `Scene::intersect`
+    fn opacity(&self, _: &Geometry) -> f64 {
+        1.0
+    }
+
+    // Whether `opacity` can be below one, so opaque hits skip the surface lookup
+    fn masked(&self) -> bool {
+        false
+    }
+
+    // Whether shadow rays pass straight through, for surfaces that only bound a medium
+    fn transparent(&self) -> bool {
+        false
+    }
+
+    // Hash of the parameters, for `Scene::hash`
+    fn digest(&self) -> u64;
+}
 
 #[derive(Debug)]
-pub struct Material {
-    pub color: Energy, // Diffuse color for opaque surfaces, transmission coefficients for transparent surfaces
-    pub fresnel: Energy, // Fresnel coefficients, used for fresnel reflectivity and computing the refractive index
-    pub light: Energy, // Light emittance, used if this Material is a light source
-    pub transmit: f64, // 0 = opaque, 1 = transparent, (0-1) = tinted thin surface
-    pub gloss: f64, // Microsurface roughness (Material "polish")
-    pub metal: f64, // The metallic range of electric (1) or dielectric (0), controls energy absorption
-
-    init_absorbance: Energy, // Initd absorbance
-    init_refract: f64, // Initd index of refraction
-    init_fresnel: f64, // Initd average Fresnel value
-}
-
-impl Material {
-    // TODO: Fix all of this..
-    pub fn light(r: f64, g: f64, b: f64) -> Material {
-        Material {
-                color: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                fresnel: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                light: Energy { x: r, y: g, z: b },
-                transmit: 0.0,
-                gloss: 0.0,
-                metal: 0.0,
-                init_absorbance: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                init_refract: 0.0,
-                init_fresnel: 0.0,
-            }
-            .init()
-    }
-
-    pub fn plastic(r: f64, g: f64, b: f64, gloss: f64) -> Material {
-        Material {
-                color: Energy { x: r, y: g, z: b },
-                fresnel: Energy {
-                    x: 0.04,
-                    y: 0.04,
-                    z: 0.04,
-                },
-                light: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                transmit: 0.0,
-                gloss: gloss,
-                metal: 0.0,
-                init_absorbance: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                init_refract: 0.0,
-                init_fresnel: 0.0,
-            }
-            .init()
-    }
-
-    pub fn lambert(r: f64, g: f64, b: f64) -> Material {
-        Material {
-                color: Energy { x: r, y: g, z: b },
-                fresnel: Energy {
-                    x: 0.02,
-                    y: 0.02,
-                    z: 0.02,
-                },
-                light: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                transmit: 0.0,
-                gloss: 0.0,
-                metal: 0.0,
-                init_absorbance: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                init_refract: 0.0,
-                init_fresnel: 0.0,
-            }
-            .init()
-    }
-
-    pub fn metal(r: f64, g: f64, b: f64, gloss: f64) -> Material {
-        Material {
-                color: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                fresnel: Energy { x: r, y: g, z: b },
-                light: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                transmit: 0.0,
-                gloss: gloss,
-                metal: 1.0,
-                init_absorbance: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                init_refract: 0.0,
-                init_fresnel: 0.0,
-            }
-            .init()
-    }
-
-    pub fn glass(r: f64, g: f64, b: f64, gloss: f64) -> Material {
-        Material {
-                color: Energy { x: r, y: g, z: b },
-                fresnel: Energy {
-                    x: 0.042,
-                    y: 0.042,
-                    z: 0.042,
-                },
-                light: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                transmit: 1.0,
-                gloss: gloss,
-                metal: 0.0,
-                init_absorbance: Energy {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                init_refract: 0.0,
-                init_fresnel: 0.0,
-            }
-            .init()
+pub struct Light {
+    pub light: Texture, // Light emittance
+    pub strength: f64, // Scales the emittance, for image textures in 0-1
+    pub profile: Option<Ies>, // Angular distribution around the normal, instead of the cosine
+}
+
+impl Light {
+    pub fn new(r: f64, g: f64, b: f64) -> Light {
+        Light::textured(Texture::constant(r, g, b), 1.0)
+    }
+
+    // Screens and signs
+    pub fn textured(light: Texture, strength: f64) -> Light {
+        Light {
+            light: light,
+            strength: strength,
+            profile: None,
+        }
+    }
+
+    // A fixture facing along the normal, with the horizontal angles of the
+    // profile starting at the tangent
+    pub fn profiled(r: f64, g: f64, b: f64, profile: Ies) -> Light {
+        Light {
+            light: Texture::constant(r, g, b),
+            strength: 1.0,
+            profile: Some(profile),
+        }
+    }
+}
+
+impl Material for Light {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        coated(&g.normal, inc, 0.02, 0.0, 0.0, &black(), rng)
+    }
+
+    fn evaluate(&self, _: &Geometry, _: &Direction, _: &Direction) -> Energy {
+        black()
     }
 
-    fn init(mut self) -> Material {
-        self.init_fresnel = self.fresnel.average().max(0.02);
-        self.init_absorbance = Energy {
-            x: 2.0 - (self.color.x * 100.0).log10(),
-            y: 2.0 - (self.color.y * 100.0).log10(),
-            z: 2.0 - (self.color.z * 100.0).log10(),
+    fn pdf(&self, _: &Geometry, _: &Direction, _: &Direction) -> f64 {
+        0.0
+    }
+
+    fn emit(&self, g: &Geometry, dir: &Direction) -> Energy {
+        let out = dir.invert();
+        let cos = g.normal.dot(&out);
+        if cos <= 0.0 {
+            return black();
+        }
+
+        let falloff = match self.profile {
+            Some(ref profile) => profile.intensity(cos.min(1.0).acos(), out.dot(&g.bitangent).atan2(out.dot(&g.tangent))),
+            None => cos,
         };
-        self.init_refract = (1.0 + self.init_fresnel.sqrt()) / (1.0 - self.init_fresnel.sqrt());
+        self.light.filtered(g).amplified(self.strength * falloff)
+    }
 
-        self
+    // Radiance falling off with the cosine leaves 2/3 PI times the average of the texture
+    fn power(&self) -> f64 {
+        luminance(&self.light.lookup(0.5, 0.5, 1.0)) * self.strength * 2.0 * PI / 3.0
     }
 
-    pub fn bsdf(&self, norm: &Direction, inc: &Direction, dist: f64, rng: &mut ThreadRng) -> (bool, Direction, Energy) {
-        if inc.enters(norm) {
-            let reflect = schlick(norm, inc, self.init_fresnel, 0.0, 0.0);
+    fn albedo(&self, _: &Geometry) -> Energy {
+        black()
+    }
 
-            // https://doc.rust-lang.org/1.2.0/book/box-syntax-and-patterns.html
-            // See above link for matcher with if
-            if rng.gen_range(0.0, 1.0) < reflect {
-                self.reflect(norm, inc, rng)
-            } else if rng.gen_range(0.0, 1.0) < self.transmit {
-                self.transmit(norm, inc, rng)
-            } else if rng.gen_range(0.0, 1.0) < self.metal {
-                self.absorb(inc)
-            } else {
-                self.diffuse(norm, inc, rng)
-            }
-        } else {
-            self.exit(norm, inc, dist, rng)
+    fn digest(&self) -> u64 {
+        Digest::new("Light")
+            .with(self.light.digest())
+            .value(self.strength)
+            .with(self.profile.as_ref().map_or(0, |p| p.digest()))
+            .finish()
+    }
+}
+
+// Diffuse surface with a faint glossy coat. A roughness above zero turns the
+// Lambertian base into Oren-Nayar, for clay, concrete and fabric.
+#[derive(Debug)]
+pub struct Lambert {
+    pub color: Texture,
+    pub roughness: Texture, // Standard deviation of the facet slopes in radians
+}
+
+impl Lambert {
+    pub fn new(r: f64, g: f64, b: f64) -> Lambert {
+        Lambert {
+            color: Texture::constant(r, g, b),
+            roughness: Texture::value(0.0),
+        }
+    }
+
+    pub fn rough(r: f64, g: f64, b: f64, roughness: f64) -> Lambert {
+        Lambert {
+            color: Texture::constant(r, g, b),
+            roughness: Texture::value(roughness),
+        }
+    }
+}
+
+impl Material for Lambert {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        coated(&g.normal, inc, 0.02, 0.0, self.roughness.filtered_scalar(g), &self.color.filtered(g), rng)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        coated_evaluate(&g.normal, inc, out, 0.02, self.roughness.filtered_scalar(g), &self.color.filtered(g))
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        coated_pdf(&g.normal, inc, out, 0.02)
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.color.filtered(g)
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Lambert").with(self.color.digest()).with(self.roughness.digest()).finish()
+    }
+}
+
+// Diffuse surface under a dielectric coat of variable polish
+#[derive(Debug)]
+pub struct Plastic {
+    pub color: Texture,
+    pub gloss: Texture, // Microsurface roughness (Material "polish")
+}
+
+impl Plastic {
+    pub fn new(r: f64, g: f64, b: f64, gloss: f64) -> Plastic {
+        Plastic {
+            color: Texture::constant(r, g, b),
+            gloss: Texture::value(gloss),
         }
     }
+}
+
+impl Material for Plastic {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        coated(&g.normal, inc, 0.04, self.gloss.filtered_scalar(g), 0.0, &self.color.filtered(g), rng)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        coated_evaluate(&g.normal, inc, out, 0.04, 0.0, &self.color.filtered(g))
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        coated_pdf(&g.normal, inc, out, 0.04)
+    }
 
-    pub fn emit(&self, normal: &Direction, dir: &Direction) -> Energy {
-        let cos = normal.dot(&dir.invert()).max(0.0);
-        self.light.amplified(cos)
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.color.filtered(g)
     }
 
-    fn reflect(&self, norm: &Direction, inc: &Direction, rng: &mut ThreadRng) -> (bool, Direction, Energy) {
-        let refl = inc.reflected(norm).cone(1.0 - self.gloss, rng);
-        if refl.enters(norm) {
-            self.diffuse(norm, inc, rng)
+    fn digest(&self) -> u64 {
+        Digest::new("Plastic").with(self.color.digest()).with(self.gloss.digest()).finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct Metal {
+    pub fresnel: Texture, // Reflectance at normal incidence
+    pub gloss: Texture,
+}
+
+impl Metal {
+    pub fn new(r: f64, g: f64, b: f64, gloss: f64) -> Metal {
+        Metal {
+            fresnel: Texture::constant(r, g, b),
+            gloss: Texture::value(gloss),
+        }
+    }
+}
+
+impl Material for Metal {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        let norm = &g.normal;
+        if !inc.enters(norm) {
+            return pass(inc);
+        }
+
+        let fresnel = self.fresnel.filtered(g);
+        if rng.gen_range(0.0, 1.0) < schlick(norm, inc, fresnel.average().max(0.02), 0.0, 0.0) {
+            reflect(norm, inc, self.gloss.filtered_scalar(g), &fresnel, &black(), rng)
         } else {
-            (true, refl, Energy{x: 1.0, y: 1.0, z: 1.0}.lerp(&self.fresnel, self.metal))
+            absorb(inc)
+        }
+    }
+
+    fn evaluate(&self, _: &Geometry, _: &Direction, _: &Direction) -> Energy {
+        black()
+    }
+
+    fn pdf(&self, _: &Geometry, _: &Direction, _: &Direction) -> f64 {
+        0.0
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.fresnel.filtered(g)
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Metal").with(self.fresnel.digest()).with(self.gloss.digest()).finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct Glass {
+    pub color: Texture, // Transmission coefficients
+    pub gloss: Texture,
+    pub film: Option<ThinFilm>, // Replaces the reflectance of the surface
+    refract: f64,
+}
+
+impl Glass {
+    pub fn new(r: f64, g: f64, b: f64, gloss: f64) -> Glass {
+        Glass {
+            color: Texture::constant(r, g, b),
+            gloss: Texture::value(gloss),
+            film: None,
+            refract: refractive_index(0.042),
         }
     }
 
-    fn transmit(&self, norm: &Direction, inc: &Direction, rng: &mut ThreadRng) -> (bool, Direction, Energy) {
-        let (entered, refr) = inc.refracted(norm, 1.0, self.init_refract);
+    fn transmit(&self, norm: &Direction, inc: &Direction, gloss: f64, color: &Energy, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        let (entered, refr) = inc.refracted(norm, 1.0, self.refract);
 
         if entered {
-            let spread = refr.cone(1.0 - self.gloss, rng);
+            let spread = refr.cone(1.0 - gloss, rng);
 
             if spread.enters(norm) {
-                (true, spread, Energy{x: 1.0, y: 1.0, z: 1.0})
+                (true, spread, white(), true)
             } else {
-                (true, refr, Energy{x: 1.0, y: 1.0, z: 1.0})
+                (true, refr, white(), true)
             }
         } else {
-            self.diffuse(norm, inc, rng)
+            diffuse(norm, color, rng)
         }
     }
 
-    // TODO: Replace these with beautiful pattern matches
-    // And some kind of interaction trait? They are all very similar
-    fn exit(&self, norm: &Direction, inc: &Direction, dist: f64, rng: &mut ThreadRng) -> (bool, Direction, Energy) {
-        if self.transmit == 0.0 {
-            return (true, Direction{x: inc.x, y: inc.y, z: inc.z}, Energy{x: 1.0, y: 1.0, z: 1.0})
-        }
+    fn exit(&self, norm: &Direction, inc: &Direction, dist: f64, gloss: f64, color: &Energy, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        let absorbance = absorbance(color);
 
-        if rng.gen_range(0.0, 1.0) >= schlick(norm, inc, 0.0, self.init_refract, 1.0) {
-            let (exited, refr) = inc.refracted(&norm.invert(), self.init_refract, 1.0);
+        if rng.gen_range(0.0, 1.0) >= schlick(norm, inc, 0.0, self.refract, 1.0) {
+            let (exited, refr) = inc.refracted(&norm.invert(), self.refract, 1.0);
             if exited {
-                let spread = refr.cone(1.0 - self.gloss, rng);
+                let spread = refr.cone(1.0 - gloss, rng);
 
                 if spread.enters(norm) {
-                    return (true, spread, beers(dist, &self.init_absorbance));
+                    return (true, spread, beers(dist, &absorbance), true);
                 }
 
-                return (true, refr, beers(dist, &self.init_absorbance));
+                return (true, refr, beers(dist, &absorbance), true);
             }
         }
 
-        (true, inc.reflected(&norm.invert()), beers(dist, &self.init_absorbance))
+        (true, inc.reflected(&norm.invert()), beers(dist, &absorbance), true)
     }
+}
+
+impl Material for Glass {
+    fn sample(&self, g: &Geometry, inc: &Direction, dist: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        let norm = &g.normal;
+        let gloss = self.gloss.filtered_scalar(g);
+        let color = self.color.filtered(g);
+
+        // The absorption of the whole path inside uses the color where it leaves
+        if !inc.enters(norm) {
+            return self.exit(norm, inc, dist, gloss, &color, rng);
+        }
 
-    // How can incoming not matter at all when diffusing?
-    fn diffuse(&self, norm: &Direction, _: &Direction, rng: &mut ThreadRng) -> (bool, Direction, Energy) {
-        (true, norm.random_hemi_cos(rng), self.color.amplified(1.0 / PI))
+        let (chance, reflected) = match self.film {
+            Some(ref film) => {
+                let eta = white().amplified(self.refract);
+                let r = film.rgb(-norm.cos(inc), &eta, &black());
+                (r.average(), r)
+            }
+            None => {
+                let r = schlick(norm, inc, 0.042, 0.0, 0.0);
+                (r, white().amplified(r))
+            }
+        };
+
+        // The reflection is chosen by its average, the color it leaves is kept as strength
+        if rng.gen_range(0.0, 1.0) < chance {
+            reflect(norm, inc, gloss, &reflected.amplified(1.0 / chance), &color, rng)
+        } else {
+            let (ok, out, strength, singular) = self.transmit(norm, inc, gloss, &color, rng);
+            let transmitted = (&white() - &reflected).amplified(1.0 / (1.0 - chance));
+            (ok, out, &strength * &transmitted, singular)
+        }
     }
 
-    fn absorb(&self, inc: &Direction,) -> (bool, Direction, Energy) {
-        (false, Direction{x: inc.x, y: inc.y, z: inc.z}, Energy{x: 0.0, y: 0.0, z: 0.0})
+    fn evaluate(&self, _: &Geometry, _: &Direction, _: &Direction) -> Energy {
+        black()
+    }
+
+    fn pdf(&self, _: &Geometry, _: &Direction, _: &Direction) -> f64 {
+        0.0
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.color.filtered(g)
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Glass")
+            .with(self.color.digest())
+            .with(self.gloss.digest())
+            .with(self.film.as_ref().map_or(0, |f| f.digest()))
+            .value(self.refract)
+            .finish()
     }
 }
 
-fn schlick(incident: &Direction, normal: &Direction, mut r0: f64, n1: f64, n2: f64) -> f64 {
+// Fresnel weighted choice between a glossy reflection and a diffuse bounce,
+// shared by the opaque dielectric presets
+fn coated(norm: &Direction, inc: &Direction, f0: f64, gloss: f64, roughness: f64, color: &Energy, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+    if !inc.enters(norm) {
+        return pass(inc);
+    }
+
+    if rng.gen_range(0.0, 1.0) < schlick(norm, inc, f0, 0.0, 0.0) {
+        reflect(norm, inc, gloss, &white(), color, rng)
+    } else {
+        rough_diffuse(norm, inc, roughness, color, rng)
+    }
+}
+
+fn coated_evaluate(norm: &Direction, inc: &Direction, out: &Direction, f0: f64, roughness: f64, color: &Energy) -> Energy {
+    if !inc.enters(norm) || out.enters(norm) {
+        return black();
+    }
+
+    color.amplified((1.0 - schlick(norm, inc, f0, 0.0, 0.0)) * oren_nayar(norm, inc, out, roughness) / PI)
+}
+
+fn coated_pdf(norm: &Direction, inc: &Direction, out: &Direction, f0: f64) -> f64 {
+    if !inc.enters(norm) || out.enters(norm) {
+        return 0.0;
+    }
+
+    (1.0 - schlick(norm, inc, f0, 0.0, 0.0)) * norm.cos(out) / PI
+}
+
+// Glossy reflection tinted by `tint`, falls back to a diffuse bounce if the
+// spread sends the ray into the surface
+pub fn reflect(norm: &Direction, inc: &Direction, gloss: f64, tint: &Energy, color: &Energy, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+    let refl = inc.reflected(norm).cone(1.0 - gloss, rng);
+    if refl.enters(norm) {
+        diffuse(norm, color, rng)
+    } else {
+        (true, refl, tint.clone(), true)
+    }
+}
+
+// Lambertian bounce, the incoming direction does not matter. See `rough_diffuse`
+// for surfaces where it does. Cosine weighted, so the strength is the color.
+pub fn diffuse(norm: &Direction, color: &Energy, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+    (true, norm.random_hemi_cos(rng), color.clone(), false)
+}
+
+// Diffuse bounce off a surface of tiny Lambertian facets, rough surfaces scatter
+// more light back towards where it came from
+pub fn rough_diffuse(norm: &Direction, inc: &Direction, roughness: f64, color: &Energy, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+    let (ok, out, strength, singular) = diffuse(norm, color, rng);
+    let factor = oren_nayar(norm, inc, &out, roughness);
+    (ok, out, strength.amplified(factor), singular)
+}
+
+// Qualitative Oren-Nayar model relative to Lambert, `roughness` is the standard
+// deviation of the facet angle in radians. Equals one for a smooth surface.
+pub fn oren_nayar(norm: &Direction, inc: &Direction, out: &Direction, roughness: f64) -> f64 {
+    if roughness <= 0.0 {
+        return 1.0;
+    }
+
+    let sigma2 = roughness * roughness;
+    let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+    let view = inc.invert();
+    let (cos_o, cos_i) = (norm.cos(&view).min(1.0), norm.cos(out).min(1.0));
+    let (sin_o, sin_i) = ((1.0 - cos_o * cos_o).max(0.0).sqrt(), (1.0 - cos_i * cos_i).max(0.0).sqrt());
+
+    // Cosine of the azimuth between the directions projected onto the surface
+    let mut cos_phi = 0.0;
+    if sin_o > 1e-6 && sin_i > 1e-6 {
+        let po = &view - &(norm * cos_o);
+        let pi = out - &(norm * cos_i);
+        cos_phi = (po.dot(&pi) / (sin_o * sin_i)).max(0.0);
+    }
+
+    // sin(alpha) * tan(beta) with alpha the larger and beta the smaller angle to the normal
+    let (sin_alpha, tan_beta) = if cos_i > cos_o {
+        (sin_o, sin_i / cos_i)
+    } else {
+        (sin_i, sin_o / cos_o.max(1e-6))
+    };
+
+    a + b * cos_phi * sin_alpha * tan_beta
+}
+
+pub fn absorb(inc: &Direction) -> (bool, Direction, Energy, bool) {
+    (false, inc.clone(), black(), false)
+}
+
+// Opaque materials let rays leaving their inside continue unchanged
+pub fn pass(inc: &Direction) -> (bool, Direction, Energy, bool) {
+    (true, inc.clone(), white(), true)
+}
+
+pub fn schlick(incident: &Direction, normal: &Direction, mut r0: f64, n1: f64, n2: f64) -> f64 {
     let mut cos_x = -normal.dot(incident);
 
     if r0 == 0.0 {
@@ -270,7 +502,21 @@ fn schlick(incident: &Direction, normal: &Direction, mut r0: f64, n1: f64, n2: f
     r0 + (1.0 - r0) * x * x * x * x * x
 }
 
-fn beers(dist: f64, absorb: &Energy) -> Energy {
+// Index of refraction of a dielectric with the given reflectance at normal incidence
+pub fn refractive_index(f0: f64) -> f64 {
+    (1.0 + f0.sqrt()) / (1.0 - f0.sqrt())
+}
+
+// Absorbance per unit distance of a medium transmitting `color`
+fn absorbance(color: &Energy) -> Energy {
+    Energy {
+        x: 2.0 - (color.x * 100.0).log10(),
+        y: 2.0 - (color.y * 100.0).log10(),
+        z: 2.0 - (color.z * 100.0).log10(),
+    }
+}
+
+pub fn beers(dist: f64, absorb: &Energy) -> Energy {
     let red = (-absorb.x * dist).exp();
     let green = (-absorb.y * dist).exp();
     let blue = (-absorb.z * dist).exp();
@@ -280,3 +526,97 @@ fn beers(dist: f64, absorb: &Energy) -> Energy {
         z: blue,
     }
 }
+
+pub fn white() -> Energy {
+    Energy { x: 1.0, y: 1.0, z: 1.0 }
+}
+
+pub fn black() -> Energy {
+    Energy { x: 0.0, y: 0.0, z: 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Material, Light, Lambert, Plastic, oren_nayar, white};
+    use ies::Ies;
+    use surface::Geometry;
+    use direction::Direction;
+    use energy::Energy;
+    use rand::{SeedableRng, XorShiftRng};
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    // Diffuse bounces are weighted by what direct lighting would find for them
+    fn consistent_sampling(mat: &Material) {
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.6, y: 0.0, z: -0.8 };
+        let mut rng = XorShiftRng::from_seed([3, 1, 4, 1]);
+        let mut diffuse = 0;
+
+        for _ in 0..1000 {
+            let (ok, out, weight, singular) = mat.sample(&g, &inc, 1.0, &mut rng);
+            assert!(ok);
+            if singular {
+                assert_eq!(weight, white());
+                continue;
+            }
+            let expected = mat.evaluate(&g, &inc, &out).amplified(g.normal.cos(&out) / mat.pdf(&g, &inc, &out));
+            assert!((&weight - &expected).len() < 1e-9, "{:?} {:?}", weight, expected);
+            diffuse += 1;
+        }
+        assert!(diffuse > 900);
+    }
+
+    #[test]
+    fn diffuse_weight() {
+        consistent_sampling(&Lambert::new(0.8, 0.5, 0.2));
+        consistent_sampling(&Lambert::rough(0.8, 0.5, 0.2, 0.6));
+        consistent_sampling(&Plastic::new(0.8, 0.5, 0.2, 0.9));
+    }
+
+    #[test]
+    fn lambert_albedo() {
+        // Reflects its color plus the 2% coat head on, not the color / PI of
+        // the material struct it replaced
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.0, y: 0.0, z: -1.0 };
+        let mat = Lambert::new(0.8, 0.5, 0.2);
+        let mut rng = XorShiftRng::from_seed([1, 4, 1, 4]);
+        let n = 10000;
+        let mut total = Energy { x: 0.0, y: 0.0, z: 0.0 };
+
+        for _ in 0..n {
+            let (ok, _, weight, _) = mat.sample(&g, &inc, 1.0, &mut rng);
+            assert!(ok);
+            total = &total + &weight;
+        }
+
+        let albedo = total.amplified(1.0 / n as f64);
+        let expected = Energy { x: 0.804, y: 0.51, z: 0.216 };
+        assert!((&albedo - &expected).len() < 0.01, "{:?}", albedo);
+    }
+
+    #[test]
+    fn oren_nayar_backscatter() {
+        let norm = Direction { x: 0.0, y: 1.0, z: 0.0 };
+        let inc = Direction { x: 0.6, y: -0.8, z: 0.0 };
+        let back = Direction { x: -0.6, y: 0.8, z: 0.0 };
+        let forward = Direction { x: 0.6, y: 0.8, z: 0.0 };
+
+        assert_eq!(1.0, oren_nayar(&norm, &inc, &back, 0.0));
+        assert!(oren_nayar(&norm, &inc, &back, 0.5) > oren_nayar(&norm, &inc, &forward, 0.5));
+        assert!(oren_nayar(&norm, &inc, &forward, 0.5) < 1.0);
+    }
+
+    #[test]
+    fn light_profile() {
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let down = Direction { x: 0.0, y: 0.0, z: -1.0 };
+        let slanted = Direction { x: 0.0, y: -FRAC_1_SQRT_2, z: -FRAC_1_SQRT_2 };
+
+        // The spot is half as bright at 45 degrees, where a diffuse emitter keeps 0.7
+        let spot = Light::profiled(1.0, 1.0, 1.0, Ies::parse("TILT=NONE\n1 1000 1 3 1 1 2 0 0 0 1 1 0 0 45 90 0 10 5 0").unwrap());
+        assert!((spot.emit(&g, &down).x - 1.0).abs() < 1e-9);
+        assert!((spot.emit(&g, &slanted).x - 0.5).abs() < 1e-3);
+        assert!((Light::new(1.0, 1.0, 1.0).emit(&g, &slanted).x - FRAC_1_SQRT_2).abs() < 1e-3);
+    }
+}
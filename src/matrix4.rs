@@ -1,6 +1,7 @@
 use direction::Direction;
 use vector3::Vector3;
 use ray3::Ray3;
+use encode::Digest;
 
 const Y_AXIS: Vector3 = Direction {
     x: 0.0,
@@ -12,6 +13,7 @@ const Y_AXIS: Vector3 = Direction {
 pub struct Matrix4([[f64; 4]; 4]);
 
 impl Matrix4 {
+    #[allow(clippy::too_many_arguments)]
     fn new(a1: f64,
            a2: f64,
            a3: f64,
@@ -39,6 +41,10 @@ impl Matrix4 {
         Matrix4::new(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0)
     }
 
+    pub fn digest(&self) -> u64 {
+        self.0.iter().fold(Digest::new("Matrix4"), |d, row| d.values(row)).finish()
+    }
+
     pub fn translation(x: f64, y: f64, z: f64) -> Matrix4 {
         Matrix4::new(1.0, 0.0, 0.0, x, 0.0, 1.0, 0.0, y, 0.0, 0.0, 1.0, z, 0.0, 0.0, 0.0, 1.0)
     }
@@ -0,0 +1,365 @@
+use energy::Energy;
+use direction::Direction;
+use vector3::Vector3;
+use microfacet::Frame;
+use scene::Scene;
+use ray3::Ray3;
+use grid::Grid;
+use encode::Digest;
+use rand::{Rng, XorShiftRng};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// Scattering events of a single walk before the path is given up
+const MAX_SCATTER: usize = 256;
+
+// Participating medium, coefficients per unit distance. A grid scales them by
+// its density, otherwise the medium is homogeneous.
+#[derive(Debug, Clone)]
+pub struct Medium {
+    pub absorption: Energy,
+    pub scattering: Energy,
+    pub g: f64, // Henyey-Greenstein asymmetry, positive scatters forward
+    pub grid: Option<Arc<Grid>>,
+}
+
+impl Medium {
+    pub fn new(absorption: Energy, scattering: Energy, g: f64) -> Medium {
+        Medium {
+            absorption: absorption,
+            scattering: scattering,
+            g: g,
+            grid: None,
+        }
+    }
+
+    // Hash of the coefficients and the grid
+    pub fn digest(&self) -> u64 {
+        Digest::new("Medium")
+            .vector(&self.absorption)
+            .vector(&self.scattering)
+            .value(self.g)
+            .with(self.grid.as_ref().map_or(0, |grid| grid.digest()))
+            .finish()
+    }
+
+    // Smoke and clouds, with the coefficients at density one
+    pub fn varying(mut self, grid: Grid) -> Medium {
+        self.grid = Some(Arc::new(grid));
+        self
+    }
+
+    // From the color of the material after many scattering events and the mean
+    // distance between them, using the albedo inversion of Chiang et al. (2016)
+    pub fn subsurface(albedo: &Energy, mean_free_path: &Energy, g: f64) -> Medium {
+        let single = |a: f64| {
+            let a = a.clamp(0.0, 1.0);
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            1.0 - s * s
+        };
+        let extinction = Energy {
+            x: 1.0 / mean_free_path.x,
+            y: 1.0 / mean_free_path.y,
+            z: 1.0 / mean_free_path.z,
+        };
+        let scattering = Energy {
+            x: single(albedo.x) * extinction.x,
+            y: single(albedo.y) * extinction.y,
+            z: single(albedo.z) * extinction.z,
+        };
+
+        Medium {
+            absorption: &extinction - &scattering,
+            scattering: scattering,
+            g: g,
+            grid: None,
+        }
+    }
+
+    pub fn extinction(&self) -> Energy {
+        &self.absorption + &self.scattering
+    }
+
+    pub fn transmittance(&self, dist: f64) -> Energy {
+        let e = self.extinction();
+        Energy {
+            x: (-e.x * dist).exp(),
+            y: (-e.y * dist).exp(),
+            z: (-e.z * dist).exp(),
+        }
+    }
+
+    // Samples the distance to the next scattering event along a ray that leaves the
+    // medium after `dist`. Returns whether the ray scattered before that, the distance
+    // travelled and the strength the signal is scaled by. The distance follows a
+    // randomly chosen channel, weighted against all three.
+    pub fn sample(&self, dist: f64, rng: &mut XorShiftRng) -> (bool, f64, Energy) {
+        let e = self.extinction();
+        let channel = match rng.gen_range(0, 3) {
+            0 => e.x,
+            1 => e.y,
+            _ => e.z,
+        };
+
+        let t = if channel > 0.0 {
+            -(1.0 - rng.gen_range::<f64>(0.0, 1.0)).ln() / channel
+        } else {
+            f64::INFINITY
+        };
+
+        if t < dist {
+            let tr = self.transmittance(t);
+            let pdf = (e.x * tr.x + e.y * tr.y + e.z * tr.z) / 3.0;
+            (true, t, (&self.scattering * &tr).amplified(1.0 / pdf))
+        } else {
+            let tr = self.transmittance(dist);
+            let pdf = tr.average();
+            if pdf == 0.0 {
+                return (false, dist, Energy { x: 0.0, y: 0.0, z: 0.0 });
+            }
+            (false, dist, tr.amplified(1.0 / pdf))
+        }
+    }
+
+    // Light left after travelling along `ray` for `dist`, estimated by ratio
+    // tracking through a grid
+    pub fn transmittance_along(&self, ray: &Ray3, dist: f64, rng: &mut XorShiftRng) -> Energy {
+        let grid = match self.grid {
+            Some(ref grid) => grid,
+            None => return self.transmittance(dist),
+        };
+        let mut tr = Energy { x: 1.0, y: 1.0, z: 1.0 };
+        let (majorant, start, end) = match self.majorant(grid, ray, dist) {
+            Some(bounds) => bounds,
+            None => return tr,
+        };
+
+        let mut t = start;
+        loop {
+            t -= (1.0 - rng.gen_range::<f64>(0.0, 1.0)).ln() / majorant;
+            if t >= end {
+                return tr;
+            }
+
+            let extinction = self.extinction().amplified(grid.density(&ray.moved(t)));
+            tr = Energy {
+                x: tr.x * (1.0 - extinction.x / majorant),
+                y: tr.y * (1.0 - extinction.y / majorant),
+                z: tr.z * (1.0 - extinction.z / majorant),
+            };
+
+            // Russian roulette once little light is left
+            if tr.max() < 0.1 {
+                let survive = tr.max();
+                if rng.gen_range(0.0, 1.0) >= survive {
+                    return Energy { x: 0.0, y: 0.0, z: 0.0 };
+                }
+                tr = tr.amplified(1.0 / survive);
+            }
+        }
+    }
+
+    // Delta tracking through a grid (Woodcock), spectrally after Kutz et al. (2017).
+    // Works like `sample`, and adds the light emitted along the way to `light`.
+    fn track(&self, grid: &Grid, ray: &Ray3, dist: f64, signal: &mut Energy, light: &mut Energy, rng: &mut XorShiftRng) -> (bool, f64) {
+        let (majorant, start, end) = match self.majorant(grid, ray, dist) {
+            Some(bounds) => bounds,
+            None => return (false, dist),
+        };
+
+        let mut t = start;
+        loop {
+            t -= (1.0 - rng.gen_range::<f64>(0.0, 1.0)).ln() / majorant;
+            if t >= end {
+                return (false, dist);
+            }
+
+            let point = ray.moved(t);
+            let density = grid.density(&point);
+            let absorption = self.absorption.amplified(density);
+            let scattering = self.scattering.amplified(density);
+            if let Some(emission) = grid.emission(&point) {
+                *light = light.merged(&(&absorption * &emission).amplified(1.0 / majorant), signal);
+            }
+
+            // Scatters with the chance of the average scattering coefficient,
+            // otherwise carries on past a null collision
+            if rng.gen_range(0.0, 1.0) * majorant < scattering.average() {
+                *signal = &*signal * &scattering.amplified(1.0 / scattering.average());
+                return (true, t);
+            }
+
+            let null = Energy {
+                x: majorant - absorption.x - scattering.x,
+                y: majorant - absorption.y - scattering.y,
+                z: majorant - absorption.z - scattering.z,
+            };
+            let rest = majorant - scattering.average();
+            *signal = &*signal * &null.amplified(1.0 / rest);
+            if signal.max() <= 0.0 {
+                return (false, t);
+            }
+        }
+    }
+
+    // Extinction bounding the grid along `ray`, and where the ray crosses it
+    fn majorant(&self, grid: &Grid, ray: &Ray3, dist: f64) -> Option<(f64, f64, f64)> {
+        let majorant = self.extinction().max() * grid.max;
+        if majorant <= 0.0 {
+            return None;
+        }
+        grid.span(ray, dist).map(|(start, end)| (majorant, start, end))
+    }
+
+    // Phase function for light travelling along `inc` scattered into `out`
+    pub fn phase(&self, inc: &Direction, out: &Direction) -> f64 {
+        hg(inc.dot(out), self.g)
+    }
+
+    // Samples a new direction proportional to the phase function, which it cancels out
+    pub fn sample_phase(&self, inc: &Direction, rng: &mut XorShiftRng) -> Direction {
+        let (u, v): (f64, f64) = (rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+        let g = self.g;
+
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        Frame::new(inc).world(&Direction {
+            x: sin * phi.cos(),
+            y: sin * phi.sin(),
+            z: cos,
+        })
+    }
+}
+
+// Henyey-Greenstein phase function of the angle between the travel directions
+pub fn hg(cos: f64, g: f64) -> f64 {
+    let d = 1.0 + g * g - 2.0 * g * cos;
+    (1.0 - g * g) / (4.0 * PI * d * d.sqrt())
+}
+
+// Follows `ray` through the medium until it reaches a surface of the scene, scattering
+// on the way, or leaves it towards nothing. `scattered` is called at every scattering
+// event with the point, the direction the light arrived along and the signal there.
+// Light emitted by the medium is added to `light`. Returns false if the path is lost
+// inside, and the number of rays cast.
+pub fn walk<F>(scene: &Scene, medium: &Medium, ray: &mut Ray3, signal: &mut Energy, light: &mut Energy, rng: &mut XorShiftRng, scattered: &mut F) -> (bool, usize)
+    where F: FnMut(&Vector3, &Direction, &Energy, &mut XorShiftRng)
+{
+    for rays in 1..MAX_SCATTER + 1 {
+        let dist = match scene.intersect(ray) {
+            Some((_, d)) => d,
+            None => return (true, rays),
+        };
+        let (inside, t) = match medium.grid {
+            Some(ref grid) => medium.track(grid, ray, dist, signal, light, rng),
+            None => {
+                let (inside, t, weight) = medium.sample(dist, rng);
+                *signal = &*signal * &weight;
+                (inside, t)
+            }
+        };
+
+        if signal.max() <= 0.0 {
+            return (false, rays);
+        }
+        if !inside {
+            return (true, rays);
+        }
+
+        let point = ray.moved(t);
+        scattered(&point, &ray.direction, signal, rng);
+
+        // Russian roulette once the signal has faded
+        let survive = signal.max().min(1.0);
+        if rng.gen_range(0.0, 1.0) >= survive {
+            return (false, rays);
+        }
+        *signal = signal.amplified(1.0 / survive);
+
+        *ray = Ray3 {
+            origin: point,
+            direction: medium.sample_phase(&ray.direction, rng),
+        };
+    }
+
+    (false, MAX_SCATTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Medium, hg};
+    use grid::Grid;
+    use energy::Energy;
+    use direction::Direction;
+    use ray3::Ray3;
+    use vector3::Vector3;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn medium_phase() {
+        // The phase function integrates to one over the sphere
+        let mut rng = XorShiftRng::from_seed([3, 1, 4, 1]);
+        let n = 100000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let cos: f64 = rng.gen_range(-1.0, 1.0);
+            sum += hg(cos, 0.7) * 4.0 * PI;
+        }
+        assert!((sum / n as f64 - 1.0).abs() < 0.05);
+
+        // Sampled directions have the mean cosine g
+        let m = Medium::new(Energy { x: 0.0, y: 0.0, z: 0.0 }, Energy { x: 1.0, y: 1.0, z: 1.0 }, 0.7);
+        let inc = Direction { x: 0.0, y: 0.0, z: 1.0 };
+        let mean: f64 = (0..n).map(|_| m.sample_phase(&inc, &mut rng).z).sum::<f64>() / n as f64;
+        assert!((mean - 0.7).abs() < 0.01);
+    }
+
+    #[test]
+    fn medium_albedo_inversion() {
+        let white = Medium::subsurface(&Energy { x: 1.0, y: 1.0, z: 1.0 }, &Energy { x: 0.1, y: 0.2, z: 0.5 }, 0.0);
+        assert!(white.absorption.max().abs() < 1e-3);
+        assert!((white.extinction().x - 10.0).abs() < 1e-9);
+
+        let gray = Medium::subsurface(&Energy { x: 0.5, y: 0.5, z: 0.5 }, &Energy { x: 1.0, y: 1.0, z: 1.0 }, 0.0);
+        assert!(gray.scattering.x > 0.5 && gray.scattering.x < 1.0);
+    }
+
+    #[test]
+    fn medium_tracking() {
+        // Glowing smoke thickening along x, one in optical depth across the grid
+        let grid = Grid::new([2, 1, 1], vec![0.0, 2.0]).fire(&[1500.0, 1500.0], 1.0);
+        let glow = grid.emission(&Vector3 { x: 0.0, y: 0.0, z: 0.0 }).unwrap();
+        let m = Medium::new(Energy { x: 1.0, y: 1.0, z: 1.0 }, Energy { x: 0.0, y: 0.0, z: 0.0 }, 0.0).varying(grid);
+        let ray = Ray3 {
+            origin: Vector3 { x: -1.0, y: 0.0, z: 0.0 },
+            direction: Direction { x: 1.0, y: 0.0, z: 0.0 },
+        };
+
+        let mut rng = XorShiftRng::from_seed([7, 7, 3, 1]);
+        let n = 20000;
+        let (mut ratio, mut delta, mut light) = (0.0, 0.0, Energy { x: 0.0, y: 0.0, z: 0.0 });
+        for _ in 0..n {
+            ratio += m.transmittance_along(&ray, 2.0, &mut rng).x;
+
+            let mut signal = Energy { x: 1.0, y: 1.0, z: 1.0 };
+            let grid = m.grid.as_ref().unwrap();
+            let (scattered, _) = m.track(grid, &ray, 2.0, &mut signal, &mut light, &mut rng);
+            assert!(!scattered);
+            delta += signal.x;
+        }
+
+        let expected = (-1.0f64).exp();
+        assert!((ratio / n as f64 - expected).abs() < 0.01, "{}", ratio / n as f64);
+        assert!((delta / n as f64 - expected).abs() < 0.01, "{}", delta / n as f64);
+        let emitted = glow.x * (1.0 - expected);
+        assert!((light.x / n as f64 - emitted).abs() < 0.02 * emitted, "{} {}", light.x / n as f64, emitted);
+    }
+}
@@ -0,0 +1,279 @@
+use energy::Energy;
+use direction::Direction;
+use distribution::Distribution;
+use material::{Material, absorb, pass, black};
+use microfacet::{Frame, reflect};
+use sample::luminance;
+use encode::{fnv_colors, Digest};
+use surface::Geometry;
+use rand::{Rng, XorShiftRng};
+use std::fs::File;
+use std::fmt;
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use std::f64::consts::{PI, FRAC_PI_2};
+
+// Channel scales of the stored values
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// Measured isotropic BRDF in the format of the MERL database (Matusik et al. 2003):
+// three little endian i32 with the number of half angle, difference angle and
+// difference azimuth bins, then the red, green and blue tables as f64. Half
+// angles are spaced quadratically to resolve the highlight.
+pub struct Merl {
+    pub dims: [usize; 3],
+    values: Vec<Energy>,
+    // Over the half angle bins, weighted by the solid angle they cover
+    distribution: Distribution,
+    digest: u64, // Of the values, taken once as they are large
+}
+
+impl Merl {
+    pub fn new(dims: [usize; 3], values: Vec<Energy>) -> Merl {
+        let (nh, nd, np) = (dims[0], dims[1], dims[2]);
+        let weights = (0..nh)
+            .map(|h| {
+                let sum: f64 = (0..nd * np).map(|i| luminance(&values[h * nd * np + i])).sum();
+                let (a, b) = (half_angle(h, nh), half_angle(h + 1, nh));
+                sum / (nd * np) as f64 * (a.cos() - b.cos())
+            })
+            .collect();
+
+        Merl {
+            dims: dims,
+            digest: fnv_colors(values.iter()),
+            values: values,
+            distribution: Distribution::new(weights),
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Merl> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut dims = [0; 3];
+        for d in dims.iter_mut() {
+            let n = read_i32(&mut r)?;
+            if n <= 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "not a MERL BRDF file"));
+            }
+            *d = n as usize;
+        }
+
+        let n = dims[0] * dims[1] * dims[2];
+        let mut channels = [Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n)];
+        for (c, channel) in channels.iter_mut().enumerate() {
+            for _ in 0..n {
+                // Missing measurements are stored as negative numbers
+                channel.push((read_f64(&mut r)? * SCALE[c]).max(0.0));
+            }
+        }
+
+        let values = (0..n)
+            .map(|i| {
+                Energy {
+                    x: channels[0][i],
+                    y: channels[1][i],
+                    z: channels[2][i],
+                }
+            })
+            .collect();
+        Ok(Merl::new(dims, values))
+    }
+
+    // Reflectance for light from `wi` seen from `wo`, in local coordinates
+    pub fn lookup(&self, wo: &Direction, wi: &Direction) -> Energy {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return black();
+        }
+
+        let h = (wo + wi).unit();
+        let theta_h = h.z.min(1.0).acos();
+        let phi_h = h.y.atan2(h.x);
+
+        // The light direction seen from the half vector
+        let (sin, cos) = (-phi_h).sin_cos();
+        let d = Direction {
+            x: wi.x * cos - wi.y * sin,
+            y: wi.x * sin + wi.y * cos,
+            z: wi.z,
+        };
+        let (sin, cos) = (-theta_h).sin_cos();
+        let d = Direction {
+            x: d.x * cos + d.z * sin,
+            y: d.y,
+            z: d.z * cos - d.x * sin,
+        };
+
+        let theta_d = d.z.clamp(-1.0, 1.0).acos();
+        let mut phi_d = d.y.atan2(d.x);
+        if phi_d < 0.0 {
+            phi_d += PI; // Reciprocity
+        }
+
+        let (nh, nd, np) = (self.dims[0], self.dims[1], self.dims[2]);
+        let ih = half_index(theta_h, nh);
+        let id = ((theta_d / FRAC_PI_2 * nd as f64) as usize).min(nd - 1);
+        let ip = ((phi_d / PI * np as f64) as usize).min(np - 1);
+        self.values[(ih * nd + id) * np + ip].clone()
+    }
+
+    // Density of sampling the half vector `h` in local coordinates
+    fn half_pdf(&self, h: &Direction) -> f64 {
+        let n = self.dims[0];
+        let i = half_index(h.z.min(1.0).acos(), n);
+        let mass = self.distribution.pdf((i as f64 + 0.5) / n as f64) / n as f64;
+        mass / (2.0 * PI * (half_angle(i, n).cos() - half_angle(i + 1, n).cos()))
+    }
+}
+
+impl Material for Merl {
+    // Picks a half angle bin by its reflectance, then a half vector uniformly
+    // over the solid angle of the bin
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        if !inc.enters(&g.normal) {
+            return pass(inc);
+        }
+
+        let frame = Frame::new(&g.normal);
+        let wo = frame.local(&inc.invert());
+        let n = self.dims[0];
+        let (x, _, i) = self.distribution.sample(rng.gen_range(0.0, 1.0));
+        let t = x * n as f64 - i as f64;
+        let (a, b) = (half_angle(i, n).cos(), half_angle(i + 1, n).cos());
+        let cos = a + (b - a) * t;
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen_range::<f64>(0.0, 1.0);
+        let h = Direction {
+            x: sin * phi.cos(),
+            y: sin * phi.sin(),
+            z: cos,
+        };
+
+        let wi = reflect(&wo, &h);
+        let dot = wo.dot(&h);
+        if wi.z <= 0.0 || dot <= 0.0 {
+            return absorb(inc);
+        }
+
+        let pdf = self.half_pdf(&h) / (4.0 * dot);
+        if pdf <= 0.0 {
+            return absorb(inc);
+        }
+        (true, frame.world(&wi), self.lookup(&wo, &wi).amplified(wi.z / pdf), false)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        let frame = Frame::new(&g.normal);
+        self.lookup(&frame.local(&inc.invert()), &frame.local(out))
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        let frame = Frame::new(&g.normal);
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let h = (&wo + &wi).unit();
+        self.half_pdf(&h) / (4.0 * wo.dot(&h))
+    }
+
+    fn albedo(&self, _: &Geometry) -> Energy {
+        let up = Direction { x: 0.0, y: 0.0, z: 1.0 };
+        self.lookup(&up, &up).amplified(PI)
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Merl").values(&[self.dims[0] as f64, self.dims[1] as f64, self.dims[2] as f64]).with(self.digest).finish()
+    }
+}
+
+// Lower edge of the half angle bin `i` of `n`
+fn half_angle(i: usize, n: usize) -> f64 {
+    let x = i as f64 / n as f64;
+    x * x * FRAC_PI_2
+}
+
+fn half_index(theta: f64, n: usize) -> usize {
+    (((theta / FRAC_PI_2).max(0.0).sqrt() * n as f64) as usize).min(n - 1)
+}
+
+fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(buf.iter().rev().fold(0u32, |n, b| (n << 8) | *b as u32) as i32)
+}
+
+fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_bits(buf.iter().rev().fold(0u64, |n, b| (n << 8) | *b as u64)))
+}
+
+// The table only shows up as its hash, as it is large
+impl fmt::Debug for Merl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Merl {{ dims: {:?}, values: {:x} }}", self.dims, self.digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Merl, SCALE};
+    use material::Material;
+    use microfacet::cosine_hemisphere;
+    use surface::Geometry;
+    use direction::Direction;
+    use furnace;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::f64::consts::PI;
+
+    // Writes a small table in the MERL format, `f` gives the value of the half angle bin
+    fn synthetic(name: &str, dims: [usize; 3], f: &Fn(usize) -> f64) -> Merl {
+        let path = env::temp_dir().join(name);
+        {
+            let mut file = File::create(&path).unwrap();
+            for &d in &dims {
+                file.write_all(&(0..4).map(|i| ((d as u32) >> (i * 8)) as u8).collect::<Vec<u8>>()).unwrap();
+            }
+            for scale in &SCALE {
+                for i in 0..dims[0] * dims[1] * dims[2] {
+                    let bits = (f(i / (dims[1] * dims[2])) / scale).to_bits();
+                    file.write_all(&(0..8).map(|i| (bits >> (i * 8)) as u8).collect::<Vec<u8>>()).unwrap();
+                }
+            }
+        }
+
+        let merl = Merl::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        merl
+    }
+
+    fn albedo(m: &Merl, inc: &Direction, rng: &mut XorShiftRng) -> (f64, f64) {
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let sampled = furnace::albedo(m, &g, inc, 100000, rng);
+        let reference = furnace::mean(100000, rng, |rng| {
+            let out = cosine_hemisphere(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+            m.evaluate(&g, inc, &out).amplified(PI)
+        });
+
+        (sampled.y, reference.y)
+    }
+
+    #[test]
+    fn merl_sampling() {
+        let mut rng = XorShiftRng::from_seed([1, 9, 9, 7]);
+        let inc = Direction { x: 0.6, y: 0.0, z: -0.8 };
+
+        let diffuse = synthetic("merl_diffuse.binary", [8, 4, 8], &|_| 0.5 / PI);
+        let (sampled, _) = albedo(&diffuse, &inc, &mut rng);
+        assert!((sampled - 0.5).abs() < 0.02, "albedo {}", sampled);
+
+        // Importance sampling a highlight agrees with uniform sampling
+        let glossy = synthetic("merl_glossy.binary", [8, 4, 8], &|h| 0.1 + 20.0 * (-(h as f64)).exp2());
+        let (sampled, reference) = albedo(&glossy, &inc, &mut rng);
+        assert!((sampled - reference).abs() < 0.05 * reference, "{} {}", sampled, reference);
+    }
+}
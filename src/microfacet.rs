@@ -0,0 +1,252 @@
+use direction::Direction;
+use std::f64::consts::PI;
+
+// Orthonormal shading frame, local coordinates have the normal along z
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub tangent: Direction,
+    pub bitangent: Direction,
+    pub normal: Direction,
+}
+
+impl Frame {
+    // Frame with an arbitrary but stable tangent
+    pub fn new(normal: &Direction) -> Frame {
+        let sign = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+        let a = -1.0 / (sign + normal.z);
+        let b = normal.x * normal.y * a;
+
+        Frame {
+            tangent: Direction {
+                x: 1.0 + sign * normal.x * normal.x * a,
+                y: sign * b,
+                z: -sign * normal.x,
+            },
+            bitangent: Direction {
+                x: b,
+                y: sign + normal.y * normal.y * a,
+                z: -normal.y,
+            },
+            normal: normal.clone(),
+        }
+    }
+
+    pub fn local(&self, v: &Direction) -> Direction {
+        Direction {
+            x: v.dot(&self.tangent),
+            y: v.dot(&self.bitangent),
+            z: v.dot(&self.normal),
+        }
+    }
+
+    pub fn world(&self, v: &Direction) -> Direction {
+        &(&(&self.tangent * v.x) + &(&self.bitangent * v.y)) + &(&self.normal * v.z)
+    }
+}
+
+// Anisotropic GGX (Trowbridge-Reitz) distribution of microfacet normals, in local coordinates
+#[derive(Debug, Clone)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Ggx {
+        Ggx {
+            alpha_x: alpha_x.max(0.001),
+            alpha_y: alpha_y.max(0.001),
+        }
+    }
+
+    // Perceptual roughness and anisotropy as used by the principled model
+    pub fn roughness(roughness: f64, anisotropic: f64) -> Ggx {
+        let aspect = (1.0 - 0.9 * anisotropic).sqrt();
+        let alpha = roughness * roughness;
+        Ggx::new(alpha / aspect, alpha * aspect)
+    }
+
+    pub fn d(&self, m: &Direction) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+
+        let x = m.x / self.alpha_x;
+        let y = m.y / self.alpha_y;
+        let k = x * x + y * y + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * k * k)
+    }
+
+    fn lambda(&self, w: &Direction) -> f64 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+
+        let a = (self.alpha_x * self.alpha_x * w.x * w.x + self.alpha_y * self.alpha_y * w.y * w.y) / (w.z * w.z);
+        ((1.0 + a).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Direction) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height correlated masking and shadowing
+    pub fn g(&self, wo: &Direction, wi: &Direction) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Samples a microfacet normal proportional to d(m) * m.z
+    pub fn sample(&self, u: f64, v: f64) -> Direction {
+        let mut phi = (self.alpha_y / self.alpha_x * (2.0 * PI * v + 0.5 * PI).tan()).atan();
+        if v > 0.5 {
+            phi += PI;
+        }
+
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let alpha2 = 1.0 / (cos_phi * cos_phi / (self.alpha_x * self.alpha_x) + sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+        let tan2 = alpha2 * u / (1.0 - u).max(1e-12);
+        let cos_theta = 1.0 / (1.0 + tan2).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+
+        Direction {
+            x: sin_theta * cos_phi,
+            y: sin_theta * sin_phi,
+            z: cos_theta,
+        }
+    }
+
+    pub fn pdf(&self, m: &Direction) -> f64 {
+        self.d(m) * m.z
+    }
+}
+
+// Mirrors `w` around `m`, both pointing away from the surface
+pub fn reflect(w: &Direction, m: &Direction) -> Direction {
+    &(m * (2.0 * w.dot(m))) - w
+}
+
+// Refracts `w` through `m` with the relative index `eta` = inside / outside.
+// Returns false on total internal reflection.
+pub fn refract(w: &Direction, m: &Direction, eta: f64) -> (bool, Direction) {
+    let cos = w.dot(m);
+    let sin2 = (1.0 - cos * cos).max(0.0) / (eta * eta);
+    if sin2 >= 1.0 {
+        return (false, w.clone());
+    }
+
+    let cos_t = (1.0 - sin2).sqrt();
+    (true, &(&w.invert() * (1.0 / eta)) + &(m * (cos / eta - cos_t)))
+}
+
+// Unpolarized Fresnel reflectance of a dielectric, `eta` = inside / outside
+pub fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let sin2 = (1.0 - cos * cos).max(0.0) / (eta * eta);
+    if sin2 >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2).sqrt();
+    let parallel = (eta * cos - cos_t) / (eta * cos + cos_t);
+    let perpendicular = (cos - eta * cos_t) / (cos + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+// Unpolarized Fresnel reflectance of a conductor with complex index `eta` + i `k`
+pub fn fresnel_conductor(cos: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos * cos;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * cos * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+
+    (parallel + perpendicular) / 2.0
+}
+
+pub fn schlick_weight(cos: f64) -> f64 {
+    let m = (1.0 - cos).clamp(0.0, 1.0);
+    m * m * m * m * m
+}
+
+// Charlie distribution of microfibers (Estevez and Kulla 2017), of the cosine
+// between the half vector and the normal
+pub fn charlie(cos: f64, alpha: f64) -> f64 {
+    let inv = 1.0 / alpha.max(0.07);
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    (2.0 + inv) * sin.powf(inv) / (2.0 * PI)
+}
+
+// Visibility term matching `charlie` (Neubelt and Pettineo 2013), including
+// the usual 1 / (4 cos_o cos_i) of microfacet models
+pub fn velvet_visibility(cos_o: f64, cos_i: f64) -> f64 {
+    1.0 / (4.0 * (cos_o + cos_i - cos_o * cos_i))
+}
+
+// Cosine weighted direction in local coordinates
+pub fn cosine_hemisphere(u: f64, v: f64) -> Direction {
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+
+    Direction {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z: (1.0 - u).max(0.0).sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, Ggx, fresnel_dielectric, fresnel_conductor};
+    use direction::Direction;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn frame_roundtrip() {
+        let n = Direction { x: 0.3, y: -0.5, z: 0.7 }.unit();
+        let frame = Frame::new(&n);
+        let v = Direction { x: 0.1, y: 0.2, z: -0.9 };
+        let back = frame.world(&frame.local(&v));
+
+        assert!((frame.local(&n).z - 1.0).abs() < 1e-9);
+        assert!((&back - &v).len() < 1e-9);
+    }
+
+    #[test]
+    fn ggx_normalized() {
+        // The projected area of the microfacets equals the macro surface
+        let ggx = Ggx::new(0.3, 0.6);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let n = 200000;
+        let mut sum = 0.0;
+
+        for _ in 0..n {
+            let z: f64 = rng.gen_range(0.0, 1.0);
+            let phi = rng.gen_range(0.0, 2.0 * PI);
+            let r = (1.0 - z * z).sqrt();
+            let m = Direction { x: r * phi.cos(), y: r * phi.sin(), z: z };
+            sum += ggx.d(&m) * m.z * 2.0 * PI;
+        }
+
+        assert!((sum / n as f64 - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn fresnel_limits() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert_eq!(1.0, fresnel_dielectric(0.1, 1.0 / 1.5));
+
+        // Without absorption a conductor is a dielectric
+        for &cos in &[1.0, 0.7, 0.3] {
+            assert!((fresnel_conductor(cos, 1.5, 0.0) - fresnel_dielectric(cos, 1.5)).abs() < 1e-9);
+        }
+        assert!((fresnel_conductor(0.0, 0.2, 3.0) - 1.0).abs() < 1e-9);
+    }
+}
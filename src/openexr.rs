@@ -0,0 +1,22 @@
+use energy::Energy;
+use exr::prelude::{Error, read_first_rgba_layer_from_file};
+use image::{ImageError, ImageResult};
+use std::path::Path;
+
+// The first layer of an OpenEXR image as linear colors, in rows from the top
+// left. Alpha is ignored.
+pub fn read(path: &Path) -> ImageResult<(usize, usize, Vec<Energy>)> {
+    let image = read_first_rgba_layer_from_file(path,
+                                                |size, _| (size.width(), vec![Energy { x: 0.0, y: 0.0, z: 0.0 }; size.area()]),
+                                                |pixels: &mut (usize, Vec<Energy>), at, (r, g, b, _): (f32, f32, f32, f32)| {
+                                                    pixels.1[at.y() * pixels.0 + at.x()] = Energy { x: r as f64, y: g as f64, z: b as f64 };
+                                                })
+        .map_err(|e| match e {
+            Error::Io(e) => ImageError::IoError(e),
+            e => ImageError::FormatError(e.to_string()),
+        })?;
+
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+    Ok((size.width(), size.height(), pixels))
+}
@@ -0,0 +1,263 @@
+use sample::Sample;
+use renderer::image;
+use image::{ImageRgb8, PNG};
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const PAGE: &str = r#"<!doctype html>
+<title>pbr</title>
+<img id="image" src="/image.png">
+<pre id="stats"></pre>
+<button onclick="fetch('/stop', {method: 'POST'})">Stop</button>
+<script>
+setInterval(function() {
+  document.getElementById('image').src = '/image.png?' + Date.now();
+  fetch('/stats').then(function(r) { return r.text(); }).then(function(t) {
+    document.getElementById('stats').textContent = t;
+  });
+}, 1000);
+</script>
+"#;
+
+// Copy of the sample buffer shared with the preview server. The renderer
+// publishes it after every pass or tile, so sampling never waits on a lock.
+#[derive(Debug)]
+pub struct Preview {
+    snapshot: Mutex<Vec<Vec<Sample>>>,
+    width: usize,
+    height: usize,
+    samples: AtomicUsize,
+    rays: AtomicUsize,
+    target: AtomicUsize,
+    stop: AtomicBool,
+    start: Instant,
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub spp: f64,
+    pub rays_per_second: f64,
+    pub eta: Option<f64>, // Seconds left until the target spp is reached
+}
+
+impl Preview {
+    pub fn new(width: usize, height: usize) -> Preview {
+        Preview {
+            snapshot: Mutex::new(vec![vec![Sample::new(); height]; width]),
+            width: width,
+            height: height,
+            samples: AtomicUsize::new(0),
+            rays: AtomicUsize::new(0),
+            target: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+            start: Instant::now(),
+        }
+    }
+
+    // Replaces the image with the current samples, `rays` were cast since the last one.
+    // The image only changes here, so the progressive renderer shows each pass
+    // once it completes. Passes double in length, late ones can keep the image
+    // unchanged for minutes unless a snapshot interval publishes in between.
+    pub fn publish(&self, samples: &[Vec<Sample>], rays: usize) -> io::Result<()> {
+        if samples.len() != self.width || samples.iter().any(|c| c.len() != self.height) {
+            return Err(Error::new(ErrorKind::InvalidInput, "samples don't match the size of the preview"));
+        }
+
+        let count = samples.iter().flat_map(|c| c.iter()).map(|s| s.count).sum();
+        self.snapshot.lock().unwrap().clone_from_slice(samples);
+        self.samples.store(count, Ordering::Relaxed);
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Samples per pixel the render is heading for, used to estimate the time left
+    pub fn set_target(&self, spp: usize) {
+        self.target.store(spp, Ordering::Relaxed);
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> Stats {
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+        let spp = self.samples.load(Ordering::Relaxed) as f64 / (self.width * self.height).max(1) as f64;
+        let target = self.target.load(Ordering::Relaxed) as f64;
+
+        Stats {
+            spp: spp,
+            rays_per_second: self.rays.load(Ordering::Relaxed) as f64 / seconds.max(1e-9),
+            eta: if target > 0.0 && spp > 0.0 {
+                Some((seconds * (target - spp) / spp).max(0.0))
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn png(&self) -> Vec<u8> {
+        let samples = self.snapshot.lock().unwrap().clone();
+        let mut buf = Vec::new();
+        ImageRgb8(image(&samples, self.width, self.height)).save(&mut buf, PNG).unwrap();
+        buf
+    }
+}
+
+// Background thread answering preview requests, runs until stopped
+#[derive(Debug)]
+pub struct Server {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Server {
+    // Waits for the request being answered, then closes the listener
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+// Serves the preview page, the current image, statistics and a stop request
+// on a background thread. The listener is polled so the server can be stopped.
+pub fn serve(preview: Arc<Preview>, listener: TcpListener) -> io::Result<Server> {
+    listener.set_nonblocking(true)?;
+    let running = Arc::new(AtomicBool::new(true));
+    let flag = running.clone();
+
+    let thread = thread::spawn(move || {
+        while flag.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let _ = stream.set_nonblocking(false).and_then(|_| respond(&preview, stream));
+                }
+                Err(_) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    });
+
+    Ok(Server {
+        running: running,
+        thread: thread,
+    })
+}
+
+fn respond(preview: &Preview, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    // Closing with unread headers resets the connection before the client reads the response
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+        header.clear();
+    }
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+
+    match (method, path) {
+        ("GET", "/") => write_response(&mut stream, "200 OK", "text/html", PAGE.as_bytes()),
+        ("GET", "/image.png") => write_response(&mut stream, "200 OK", "image/png", &preview.png()),
+        ("GET", "/stats") => {
+            let stats = preview.stats();
+            let eta = stats.eta.map_or("null".to_string(), |e| format!("{:.1}", e));
+            let body = format!("{{\"spp\": {:.2}, \"rays_per_second\": {:.0}, \"eta\": {}, \"stopped\": {}}}",
+                               stats.spp,
+                               stats.rays_per_second,
+                               eta,
+                               preview.stopped());
+            write_response(&mut stream, "200 OK", "application/json", body.as_bytes())
+        }
+        ("POST", "/stop") => {
+            preview.stop();
+            write_response(&mut stream, "200 OK", "text/plain", b"stopping\n")
+        }
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", b"not found\n"),
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+           status,
+           content_type,
+           body.len())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Preview, serve};
+    use sample::Sample;
+    use energy::Energy;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use image::load_from_memory;
+
+    fn request(addr: &::std::net::SocketAddr, request: &str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn preview_serves_stats_image_and_stop() {
+        let preview = Arc::new(Preview::new(2, 2));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = serve(preview.clone(), listener).unwrap();
+
+        let mut sample = Sample::new();
+        let white = Energy { x: 255.0, y: 255.0, z: 255.0 };
+        sample.add(&white, &white, &white);
+        sample.add(&white, &white, &white);
+        preview.set_target(4);
+        preview.publish(&vec![vec![sample; 2]; 2], 40).unwrap();
+
+        // Headers are read before answering, or closing the socket would reset it
+        let stats = String::from_utf8(request(&addr, "GET /stats HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n")).unwrap();
+        assert!(stats.starts_with("HTTP/1.1 200 OK"));
+        assert!(stats.contains("\"spp\": 2.00"));
+
+        let image = request(&addr, "GET /image.png?1 HTTP/1.1\r\n\r\n");
+        assert!(image.windows(4).any(|w| w == b"\x89PNG"));
+
+        assert!(!preview.stopped());
+        request(&addr, "POST /stop HTTP/1.1\r\n\r\n");
+        assert!(preview.stopped());
+
+        server.stop();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn preview_unsampled_black() {
+        let preview = Preview::new(2, 1);
+        let image = load_from_memory(&preview.png()).unwrap().to_rgb();
+        assert_eq!(&[0, 0, 0], &image.get_pixel(1, 0).data);
+    }
+
+    #[test]
+    fn preview_size_mismatch() {
+        let preview = Preview::new(2, 2);
+        assert!(preview.publish(&vec![vec![Sample::new(); 3]; 2], 0).is_err());
+        assert!(preview.publish(&vec![vec![Sample::new(); 2]; 3], 0).is_err());
+        assert_eq!(0.0, preview.stats().spp);
+    }
+}
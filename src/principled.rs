@@ -0,0 +1,425 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, Lambert, Plastic, Metal, Glass, refractive_index, absorb, pass, white};
+use microfacet::{Frame, Ggx, reflect, refract, fresnel_dielectric, schlick_weight, cosine_hemisphere};
+use sample::luminance;
+use surface::Geometry;
+use texture::Texture;
+use rand::{Rng, XorShiftRng};
+use std::f64::consts::PI;
+
+// Principled material following the Disney BRDF. The diffuse, sheen,
+// transmission and specular lobes sit under an optional clearcoat, and the
+// dielectric base only receives the light its specular reflection lets through.
+#[derive(Debug)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    pub specular: Texture, // Dielectric reflectance at normal incidence, 0.5 = 4%
+    pub specular_tint: Texture,
+    pub sheen: Texture,
+    pub sheen_tint: Texture,
+    pub clearcoat: Texture,
+    pub clearcoat_gloss: Texture,
+    pub transmission: Texture,
+    pub ior: f64, // Index of refraction used by transmission
+    pub anisotropic: Texture, // Rougher along the tangent
+    pub tangent: Option<Texture>, // Tangent map, see `Geometry::frame`
+}
+
+impl Principled {
+    pub fn new(r: f64, g: f64, b: f64) -> Principled {
+        Principled {
+            base_color: Texture::constant(r, g, b),
+            metallic: Texture::value(0.0),
+            roughness: Texture::value(0.5),
+            specular: Texture::value(0.5),
+            specular_tint: Texture::value(0.0),
+            sheen: Texture::value(0.0),
+            sheen_tint: Texture::value(0.5),
+            clearcoat: Texture::value(0.0),
+            clearcoat_gloss: Texture::value(1.0),
+            transmission: Texture::value(0.0),
+            ior: 1.5,
+            anisotropic: Texture::value(0.0),
+            tangent: None,
+        }
+    }
+
+    // The parameters at a surface point
+    fn bsdf(&self, g: &Geometry) -> Bsdf {
+        Bsdf {
+            base_color: self.base_color.filtered(g),
+            metallic: self.metallic.filtered_scalar(g),
+            roughness: self.roughness.filtered_scalar(g),
+            specular: self.specular.filtered_scalar(g),
+            specular_tint: self.specular_tint.filtered_scalar(g),
+            sheen: self.sheen.filtered_scalar(g),
+            sheen_tint: self.sheen_tint.filtered_scalar(g),
+            clearcoat: self.clearcoat.filtered_scalar(g),
+            clearcoat_gloss: self.clearcoat_gloss.filtered_scalar(g),
+            transmission: self.transmission.filtered_scalar(g),
+            ior: self.ior,
+            anisotropic: self.anisotropic.filtered_scalar(g),
+        }
+    }
+}
+
+impl Material for Principled {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        self.bsdf(g).sample(&g.frame(self.tangent.as_ref()), inc, rng)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        self.bsdf(g).evaluate(&g.frame(self.tangent.as_ref()), inc, out)
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        self.bsdf(g).pdf(&g.frame(self.tangent.as_ref()), inc, out)
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        self.base_color.filtered(g)
+    }
+
+    fn digest(&self) -> u64 {
+        let textures = [&self.base_color, &self.metallic, &self.roughness, &self.specular, &self.specular_tint, &self.sheen,
+                        &self.sheen_tint, &self.clearcoat, &self.clearcoat_gloss, &self.transmission, &self.anisotropic];
+        textures.iter()
+            .fold(Digest::new("Principled"), |d, t| d.with(t.digest()))
+            .value(self.ior)
+            .with(self.tangent.as_ref().map_or(0, |t| t.digest()))
+            .finish()
+    }
+}
+
+struct Bsdf {
+    base_color: Energy,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_gloss: f64,
+    transmission: f64,
+    ior: f64,
+    anisotropic: f64,
+}
+
+impl Bsdf {
+    fn ggx(&self) -> Ggx {
+        Ggx::roughness(self.roughness, self.anisotropic)
+    }
+
+    fn coat(&self) -> Ggx {
+        let alpha = 0.1 + (0.001 - 0.1) * self.clearcoat_gloss;
+        Ggx::new(alpha, alpha)
+    }
+
+    // Base color normalized to luminance 1
+    fn tint(&self) -> Energy {
+        let l = luminance(&self.base_color);
+        if l > 0.0 {
+            self.base_color.amplified(1.0 / l)
+        } else {
+            white()
+        }
+    }
+
+    fn specular0(&self) -> Energy {
+        let dielectric = white().lerp(&self.tint(), self.specular_tint).amplified(self.specular * 0.08);
+        dielectric.lerp(&self.base_color, self.metallic)
+    }
+
+    // Fraction of light passing the clearcoat
+    fn above(&self, cos: f64) -> f64 {
+        1.0 - self.clearcoat * (0.04 + 0.96 * schlick_weight(cos))
+    }
+
+    // Fraction of light passing the specular reflection of the dielectric base
+    fn below(&self, cos: f64) -> f64 {
+        let f0 = self.specular * 0.08;
+        1.0 - (f0 + (1.0 - f0) * schlick_weight(cos))
+    }
+
+    // Probabilities of sampling the diffuse, specular, clearcoat and transmission lobes
+    fn lobes(&self, cos: f64) -> [f64; 4] {
+        let above = self.above(cos);
+        let below = self.below(cos);
+        let dielectric = 1.0 - self.metallic;
+
+        let mut lobes = [above * dielectric * (1.0 - self.transmission) * below * (luminance(&self.base_color) + self.sheen),
+                         above * luminance(&self.specular0().lerp(&white(), schlick_weight(cos))),
+                         1.0 - above,
+                         above * dielectric * self.transmission * below];
+        let total: f64 = lobes.iter().sum();
+        for p in lobes.iter_mut() {
+            *p = if total > 0.0 { *p / total } else { 0.0 };
+        }
+
+        lobes
+    }
+
+    // Bsdf of the reflection lobes, both directions local and above the surface
+    fn reflection(&self, wo: &Direction, wi: &Direction) -> Energy {
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        let h = (wo + wi).unit();
+        let cos_d = wi.dot(&h);
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * schlick_weight(cos_i)) * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+        let diffuse = self.base_color.amplified(fd / PI);
+        let sheen = white().lerp(&self.tint(), self.sheen_tint).amplified(self.sheen * schlick_weight(cos_d));
+        let base = (&diffuse + &sheen).amplified((1.0 - self.metallic) * (1.0 - self.transmission) * self.below(cos_o));
+
+        let ggx = self.ggx();
+        let specular = self.specular0()
+            .lerp(&white(), schlick_weight(cos_d))
+            .amplified(ggx.d(&h) * ggx.g(wo, wi) / (4.0 * cos_i * cos_o));
+
+        let coat = self.coat();
+        let clearcoat = self.clearcoat * (0.04 + 0.96 * schlick_weight(cos_d)) * gtr1(h.z, coat.alpha_x) *
+                        Ggx::new(0.25, 0.25).g(wo, wi) / (4.0 * cos_i * cos_o);
+
+        &(&base + &specular).amplified(self.above(cos_o)) + &white().amplified(clearcoat)
+    }
+
+    fn reflection_pdf(&self, wo: &Direction, wi: &Direction, lobes: &[f64; 4]) -> f64 {
+        let h = (wo + wi).unit();
+        let jacobian = 4.0 * wo.dot(&h);
+
+        lobes[0] * wi.z / PI + lobes[1] * self.ggx().pdf(&h) / jacobian + lobes[2] * gtr1(h.z, self.coat().alpha_x) * h.z / jacobian
+    }
+
+    // Rough dielectric transmission, `wo` above and `wi` below the surface.
+    // Returns the btdf and the half vector.
+    fn transmission(&self, wo: &Direction, wi: &Direction) -> (Energy, Direction) {
+        let eta = self.ior;
+        let mut h = (wo + &(wi * eta)).unit().invert();
+        if h.z < 0.0 {
+            h = h.invert();
+        }
+
+        let (cos_oh, cos_ih) = (wo.dot(&h), wi.dot(&h));
+        if cos_oh <= 0.0 || cos_ih >= 0.0 {
+            return (Energy { x: 0.0, y: 0.0, z: 0.0 }, h);
+        }
+
+        let ggx = self.ggx();
+        let denom = cos_oh + eta * cos_ih;
+        // Includes eta^2 so refraction keeps its strength like the glass preset, the radiance
+        // scaling cancels out once the path leaves the object again
+        let f = eta * eta * (1.0 - fresnel_dielectric(cos_oh, eta)) * ggx.d(&h) * ggx.g(wo, wi) * cos_oh * -cos_ih /
+                (wo.z * -wi.z * denom * denom);
+
+        (self.base_color.amplified(f * self.above(wo.z) * (1.0 - self.metallic) * self.transmission), h)
+    }
+
+    fn transmission_pdf(&self, wo: &Direction, wi: &Direction, h: &Direction, lobes: &[f64; 4]) -> f64 {
+        let eta = self.ior;
+        let denom = wo.dot(h) + eta * wi.dot(h);
+        lobes[3] * self.ggx().pdf(h) * eta * eta * wi.dot(h).abs() / (denom * denom)
+    }
+
+    // Samples the clearcoat distribution, proportional to gtr1(m.z) * m.z
+    fn coat_sample(&self, u: f64, v: f64) -> Direction {
+        let alpha2 = self.coat().alpha_x * self.coat().alpha_x;
+        let cos = ((1.0 - alpha2.powf(1.0 - u)) / (1.0 - alpha2)).max(0.0).sqrt();
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        Direction {
+            x: sin * phi.cos(),
+            y: sin * phi.sin(),
+            z: cos,
+        }
+    }
+
+    // Leaving a transmissive object, only the dielectric interface applies
+    fn exit(&self, frame: &Frame, wo: &Direction, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        let ggx = self.ggx();
+        let m = ggx.sample(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+        let cos = wo.dot(&m);
+        if cos <= 0.0 {
+            return absorb(&frame.world(&wo.invert()));
+        }
+
+        let wi = if rng.gen_range(0.0, 1.0) < fresnel_dielectric(cos, 1.0 / self.ior) {
+            reflect(wo, &m)
+        } else {
+            refract(wo, &m, 1.0 / self.ior).1
+        };
+
+        let strength = ggx.g(wo, &wi) * cos / (wo.z * m.z);
+        (true, frame.world(&wi), white().amplified(strength), true)
+    }
+
+    fn sample(&self, frame: &Frame, inc: &Direction, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        if !inc.enters(&frame.normal) {
+            if self.transmission == 0.0 {
+                return pass(inc);
+            }
+
+            let inside = Frame {
+                tangent: frame.tangent.clone(),
+                bitangent: frame.bitangent.invert(),
+                normal: frame.normal.invert(),
+            };
+            return self.exit(&inside, &inside.local(&inc.invert()), rng);
+        }
+
+        let wo = frame.local(&inc.invert());
+        let lobes = self.lobes(wo.z);
+        let (u, v) = (rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+
+        let mut pick = rng.gen_range(0.0, 1.0);
+        let mut lobe = 0;
+        while lobe < 3 && pick >= lobes[lobe] {
+            pick -= lobes[lobe];
+            lobe += 1;
+        }
+
+        let wi = match lobe {
+            0 => cosine_hemisphere(u, v),
+            1 => reflect(&wo, &self.ggx().sample(u, v)),
+            2 => reflect(&wo, &self.coat_sample(u, v)),
+            _ => {
+                let (refracted, wi) = refract(&wo, &self.ggx().sample(u, v), self.ior);
+                if !refracted {
+                    return absorb(inc);
+                }
+                wi
+            }
+        };
+
+        let (f, pdf) = if wi.z > 0.0 {
+            (self.reflection(&wo, &wi), self.reflection_pdf(&wo, &wi, &lobes))
+        } else {
+            let (f, h) = self.transmission(&wo, &wi);
+            let pdf = self.transmission_pdf(&wo, &wi, &h, &lobes);
+            (f, pdf)
+        };
+
+        if pdf <= 0.0 || wi.z == 0.0 {
+            return absorb(inc);
+        }
+
+        (true, frame.world(&wi), f.amplified(wi.z.abs() / pdf), false)
+    }
+
+    fn evaluate(&self, frame: &Frame, inc: &Direction, out: &Direction) -> Energy {
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            Energy { x: 0.0, y: 0.0, z: 0.0 }
+        } else if wi.z > 0.0 {
+            self.reflection(&wo, &wi)
+        } else {
+            self.transmission(&wo, &wi).0
+        }
+    }
+
+    fn pdf(&self, frame: &Frame, inc: &Direction, out: &Direction) -> f64 {
+        let (wo, wi) = (frame.local(&inc.invert()), frame.local(out));
+
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        let lobes = self.lobes(wo.z);
+        if wi.z > 0.0 {
+            self.reflection_pdf(&wo, &wi, &lobes)
+        } else {
+            let (_, h) = self.transmission(&wo, &wi);
+            self.transmission_pdf(&wo, &wi, &h, &lobes)
+        }
+    }
+}
+
+// Generalized Trowbridge-Reitz with gamma 1, the clearcoat distribution
+fn gtr1(cos: f64, alpha: f64) -> f64 {
+    if cos <= 0.0 {
+        return 0.0;
+    }
+
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos * cos))
+}
+
+// Mappings from the presets, gloss becomes the inverse of roughness and the
+// preset reflectance at normal incidence becomes `specular`
+
+impl<'a> From<&'a Lambert> for Principled {
+    fn from(m: &'a Lambert) -> Principled {
+        let mut p = Principled::new(1.0, 1.0, 1.0);
+        p.base_color = m.color.clone();
+        p.roughness = Texture::value(1.0);
+        p.specular = Texture::value(0.02 / 0.08);
+        p
+    }
+}
+
+impl<'a> From<&'a Plastic> for Principled {
+    fn from(m: &'a Plastic) -> Principled {
+        let mut p = Principled::new(1.0, 1.0, 1.0);
+        p.base_color = m.color.clone();
+        p.roughness = Texture::Inverse(Box::new(m.gloss.clone()));
+        p.specular = Texture::value(0.04 / 0.08);
+        p
+    }
+}
+
+impl<'a> From<&'a Metal> for Principled {
+    fn from(m: &'a Metal) -> Principled {
+        let mut p = Principled::new(1.0, 1.0, 1.0);
+        p.base_color = m.fresnel.clone();
+        p.metallic = Texture::value(1.0);
+        p.roughness = Texture::Inverse(Box::new(m.gloss.clone()));
+        p
+    }
+}
+
+impl<'a> From<&'a Glass> for Principled {
+    fn from(m: &'a Glass) -> Principled {
+        let mut p = Principled::new(1.0, 1.0, 1.0);
+        p.base_color = m.color.clone();
+        p.roughness = Texture::Inverse(Box::new(m.gloss.clone()));
+        p.specular = Texture::value(0.042 / 0.08);
+        p.transmission = Texture::value(1.0);
+        p.ior = refractive_index(0.042);
+        p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Principled;
+    use surface::Geometry;
+    use texture::Texture;
+    use direction::Direction;
+    use furnace;
+    use rand::{SeedableRng, XorShiftRng};
+
+    // White furnace: the reflected energy never exceeds the incoming energy
+    #[test]
+    fn principled_conserves_energy() {
+        let geometry = Geometry::new(&Direction { x: 0.0, y: 1.0, z: 0.0 }, 0.0, 0.0);
+        let inc = Direction { x: 0.6, y: -0.8, z: 0.0 };
+        let mut rng = XorShiftRng::from_seed([4, 3, 2, 1]);
+
+        for &(metallic, clearcoat, transmission, sheen) in &[(0.0, 0.0, 0.0, 0.0), (1.0, 0.0, 0.0, 0.0), (0.0, 1.0, 0.0, 1.0), (0.0, 0.5, 1.0, 0.0)] {
+            let mut m = Principled::new(1.0, 1.0, 1.0);
+            m.metallic = Texture::value(metallic);
+            m.clearcoat = Texture::value(clearcoat);
+            m.transmission = Texture::value(transmission);
+            m.sheen = Texture::value(sheen);
+
+            let albedo = furnace::albedo(&m, &geometry, &inc, 100000, &mut rng).average();
+            assert!(albedo < 1.05 && albedo > 0.5, "albedo {} for {:?}", albedo, m);
+        }
+    }
+}
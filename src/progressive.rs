@@ -0,0 +1,183 @@
+use sampler::Sampler;
+use renderer::Renderer;
+use checkpoint::Checkpoint;
+use rand::{SeedableRng, XorShiftRng};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Renders in passes of doubling sample count, writing an intermediate image
+// every few passes or seconds until the time budget or target spp is reached.
+// The checkpoint, if any, is written with the snapshots and once more at the end.
+#[derive(Debug)]
+pub struct Progressive {
+    pub initial: usize, // Samples per pixel of the first pass
+    pub target: Option<usize>, // Stop once every pixel has this many samples
+    pub budget: Option<Duration>, // Stop once this much wall clock time has passed
+    pub snapshot_passes: Option<usize>, // Write a snapshot every n passes
+    pub snapshot_interval: Option<Duration>, // Write a snapshot every n seconds
+    pub path: PathBuf,
+    pub checkpoint: Option<PathBuf>, // Written alongside every snapshot
+    pub seed: u32,
+}
+
+impl Default for Progressive {
+    fn default() -> Progressive {
+        Progressive::new()
+    }
+}
+
+impl Progressive {
+    pub fn new() -> Progressive {
+        Progressive {
+            initial: 1,
+            target: None,
+            budget: None,
+            snapshot_passes: None,
+            snapshot_interval: Some(Duration::from_secs(10)),
+            path: PathBuf::from("fractal.png"),
+            checkpoint: None,
+            seed: 1,
+        }
+    }
+
+    // Returns the number of samples per pixel of the last completed pass
+    pub fn run(&self, sampler: &mut Sampler) -> io::Result<usize> {
+        self.render(sampler, self.seed, 0, 0)
+    }
+
+    // Continues from a checkpoint, the result is identical to an uninterrupted run
+    pub fn resume(&self, sampler: &mut Sampler, checkpoint: &Path) -> io::Result<usize> {
+        let (seed, pass, spp) = Checkpoint::read(checkpoint)?.restore(sampler)?;
+        self.render(sampler, seed, pass, spp)
+    }
+
+    fn render(&self, sampler: &mut Sampler, seed: u32, mut pass: usize, mut spp: usize) -> io::Result<usize> {
+        if self.initial == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the first pass needs at least one sample"));
+        }
+
+        let start = Instant::now();
+        let mut snapshot = Instant::now();
+
+        if let (Some(ref preview), Some(target)) = (sampler.preview.clone(), self.target) {
+            preview.set_target(target);
+        }
+
+        while !self.done(sampler, spp, &start) {
+            let mut samples = self.initial << pass.min(16);
+            if let Some(target) = self.target {
+                samples = samples.min(target - spp);
+            }
+
+            // Every pass has its own sequence so a resumed render picks up the same numbers
+            let mut rng = XorShiftRng::from_seed([seed, pass as u32, 0x193a6754, 0xa8a7d469]);
+            let (complete, taken) = self.pass(sampler, &mut rng, samples, &start, &mut snapshot)?;
+            if !complete {
+                break;
+            }
+
+            spp += samples;
+            pass += 1;
+            sampler.publish()?;
+
+            // Checkpoints only hold whole passes, so one taken during the pass is written now
+            let by_pass = self.snapshot_passes.is_some_and(|n| pass.is_multiple_of(n));
+            let by_time = self.snapshot_interval.is_some_and(|i| snapshot.elapsed() >= i);
+            if by_pass || by_time || taken {
+                Renderer::new(sampler).save(&sampler.samples, &self.path);
+                if let Some(ref path) = self.checkpoint {
+                    Checkpoint::new(sampler, seed, pass, spp).write(path)?;
+                }
+                snapshot = Instant::now();
+            }
+        }
+
+        sampler.publish()?;
+        Renderer::new(sampler).save(&sampler.samples, &self.path);
+        if let Some(ref path) = self.checkpoint {
+            Checkpoint::new(sampler, seed, pass, spp).write(path)?;
+        }
+
+        Ok(spp)
+    }
+
+    // Returns false if the budget ran out or a stop was requested before the pass
+    // completed, and whether a timed snapshot was written during the pass
+    fn pass(&self, sampler: &mut Sampler, rng: &mut XorShiftRng, samples: usize, start: &Instant, snapshot: &mut Instant) -> io::Result<(bool, bool)> {
+        let mut taken = false;
+
+        for x in 0..sampler.cam.width {
+            if self.budget.is_some_and(|b| start.elapsed() >= b) || stopped(sampler) {
+                return Ok((false, taken));
+            }
+
+            // Late passes take long, the interval is kept between columns
+            if self.snapshot_interval.is_some_and(|i| snapshot.elapsed() >= i) {
+                sampler.publish()?;
+                Renderer::new(sampler).save(&sampler.samples, &self.path);
+                *snapshot = Instant::now();
+                taken = true;
+            }
+
+            for y in 0..sampler.cam.height {
+                sampler.sample_pixel(x, y, rng, samples);
+            }
+        }
+
+        Ok((true, taken))
+    }
+
+    fn done(&self, sampler: &Sampler, spp: usize, start: &Instant) -> bool {
+        self.target.is_some_and(|t| spp >= t) || self.budget.is_some_and(|b| start.elapsed() >= b) || stopped(sampler)
+    }
+}
+
+// A stop requested through the preview server
+fn stopped(sampler: &Sampler) -> bool {
+    sampler.preview.as_ref().is_some_and(|p| p.stopped())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Progressive;
+    use sampler::{Sampler, SamplerConfiguration};
+    use camera::Camera;
+    use scene::Scene;
+    use surface::Surface;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn progressive_passes() {
+        let surfaces: Vec<Box<Surface>> = Vec::new();
+        let scene = Scene::new(&surfaces);
+        let camera = Camera::new(4, 2, 0.050, 0.024, 4.0);
+        let mut sampler = Sampler::new(&camera, &scene, SamplerConfiguration { max_bounces: 1, adapt: 0 });
+
+        let mut progressive = Progressive::new();
+        progressive.path = env::temp_dir().join("progressive_passes.png");
+        progressive.target = Some(7);
+        progressive.initial = 0;
+        assert!(progressive.run(&mut sampler).is_err());
+
+        // 1 + 2 + 4 samples, with a snapshot between every column
+        progressive.initial = 1;
+        progressive.snapshot_interval = Some(Duration::from_secs(0));
+        assert_eq!(progressive.run(&mut sampler).unwrap(), 7);
+
+        // The last checkpoint holds the finished render, so resuming has nothing left to do
+        let checkpoint = env::temp_dir().join("progressive_passes.ckpt");
+        progressive.checkpoint = Some(checkpoint.clone());
+        progressive.snapshot_interval = None;
+        let mut sampler = Sampler::new(&camera, &scene, SamplerConfiguration { max_bounces: 1, adapt: 0 });
+        progressive.run(&mut sampler).unwrap();
+        let mut resumed = Sampler::new(&camera, &scene, SamplerConfiguration { max_bounces: 1, adapt: 0 });
+        assert_eq!(progressive.resume(&mut resumed, &checkpoint).unwrap(), 7);
+        assert_eq!(resumed.samples[3][1].count, 7);
+        assert_eq!(resumed.samples[3][1].red, sampler.samples[3][1].red);
+        fs::remove_file(&checkpoint).unwrap();
+        fs::remove_file(&progressive.path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use material::Material;
+use matrix4::Matrix4;
+use encode::Digest;
+use vector3::Vector3;
+use surface::{Surface, Geometry};
+use ray3::Ray3;
+use direction::Direction;
+use constants::BIAS;
+use rand::{Rng, XorShiftRng};
+
+// Flat square of unit size facing along z, for thin surfaces such as leaves,
+// fences and walls. Texture coordinates follow x and y.
+#[derive(Debug)]
+pub struct Quad<'a> {
+    material: &'a Material,
+    pos: Matrix4,
+}
+
+impl<'a> Quad<'a> {
+    pub fn new(m: &'a Material) -> Quad<'a> {
+        Quad {
+            material: m,
+            pos: Matrix4::identity(),
+        }
+    }
+
+    pub fn placed(mut self, pos: Matrix4) -> Quad<'a> {
+        self.pos = pos;
+        self
+    }
+}
+
+impl<'a> Surface for Quad<'a> {
+    fn intersect(&self, r: &Ray3) -> (bool, f64) {
+        let r = self.pos.inverse().mult_ray(r);
+        if r.direction.z == 0.0 {
+            return (false, 0.0);
+        }
+
+        let t = -r.origin.z / r.direction.z;
+        let p = r.moved(t);
+        if t <= 0.0 || p.x.abs() > 0.5 || p.y.abs() > 0.5 {
+            return (false, 0.0);
+        }
+
+        let dist = self.pos.mult_dist(&(&r.direction * t)).len();
+        if dist > BIAS {
+            (true, dist)
+        } else {
+            (false, 0.0)
+        }
+    }
+
+    fn at(&self, v: &Vector3) -> (Geometry, &Material) {
+        let p = self.pos.inverse().mult_point(v);
+        let normal = self.pos.mult_dir(&Vector3 { x: 0.0, y: 0.0, z: 1.0 });
+        let dpdu = self.pos.mult_dist(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        let dpdv = self.pos.mult_dist(&Vector3 { x: 0.0, y: 1.0, z: 0.0 });
+        let tangent = dpdu.unit();
+
+        let geometry = Geometry {
+            bitangent: normal.cross(&tangent),
+            tangent: tangent,
+            geometric: normal.clone(),
+            normal: normal,
+            u: p.x + 0.5,
+            v: p.y + 0.5,
+            dpdu: dpdu,
+            dpdv: dpdv,
+            footprint: 0.0,
+        };
+
+        (geometry, self.material)
+    }
+
+    fn material(&self) -> &Material {
+        self.material
+    }
+
+    fn bounds(&self) -> (Vector3, Vector3) {
+        let inf = f64::INFINITY;
+        let mut min = Vector3 { x: inf, y: inf, z: inf };
+        let mut max = Vector3 { x: -inf, y: -inf, z: -inf };
+        for &(x, y) in &[(-0.5, -0.5), (0.5, -0.5), (-0.5, 0.5), (0.5, 0.5)] {
+            let p = self.pos.mult_point(&Vector3 { x: x, y: y, z: 0.0 });
+            min = Vector3 { x: min.x.min(p.x), y: min.y.min(p.y), z: min.z.min(p.z) };
+            max = Vector3 { x: max.x.max(p.x), y: max.y.max(p.y), z: max.z.max(p.z) };
+        }
+
+        (min, max)
+    }
+
+    fn area(&self) -> f64 {
+        let dpdu = self.pos.mult_dist(&Vector3 { x: 1.0, y: 0.0, z: 0.0 });
+        let dpdv = self.pos.mult_dist(&Vector3 { x: 0.0, y: 1.0, z: 0.0 });
+        dpdu.cross(&dpdv).len()
+    }
+
+    fn sample(&self, rng: &mut XorShiftRng) -> (Vector3, f64) {
+        let p = Vector3 {
+            x: rng.gen_range(-0.5, 0.5),
+            y: rng.gen_range(-0.5, 0.5),
+            z: 0.0,
+        };
+
+        (self.pos.mult_point(&p), 1.0 / self.area())
+    }
+
+    fn pdf(&self, _: &Vector3) -> f64 {
+        1.0 / self.area()
+    }
+
+    fn facing(&self) -> Option<Direction> {
+        Some(self.pos.mult_dir(&Vector3 { x: 0.0, y: 0.0, z: 1.0 }))
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Quad").with(self.pos.digest()).with(self.material.digest()).finish()
+    }
+}
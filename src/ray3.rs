@@ -0,0 +1,16 @@
+use vector3::Vector3;
+use direction::Direction;
+
+// A ray starting at `origin` and extending infinitely along the unit `direction`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ray3 {
+    pub origin: Vector3,
+    pub direction: Direction,
+}
+
+impl Ray3 {
+    // The point `dist` along the ray
+    pub fn moved(&self, dist: f64) -> Vector3 {
+        &self.origin + &(&self.direction * dist)
+    }
+}
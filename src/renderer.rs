@@ -1,4 +1,6 @@
 use sampler::Sampler;
+use sample::Sample;
+use denoise::Denoiser;
 use image::{ImageBuffer, ImageRgb8, PNG, Rgb};
 use std::fs::File;
 use std::path::Path;
@@ -16,22 +18,40 @@ impl<'a> Renderer<'a> {
     }
 
     pub fn png(&self) {
-        let img = ImageBuffer::from_fn(self.sampler.cam.width as u32, self.sampler.cam.height as u32, |x, y| {
-            let (x, y) = (x as usize, y as usize);
-            let count = self.sampler.samples[x][y].count as f64;
-
-            Rgb([
-                color(self.sampler.samples[x][y].red / count),
-                color(self.sampler.samples[x][y].green / count),
-                color(self.sampler.samples[x][y].blue / count)
-            ])
-        });
-
-        let ref mut fout = File::create(&Path::new("fractal.png")).unwrap();
+        self.save(&self.sampler.samples, Path::new("fractal.png"));
+    }
+
+    // Writes the denoised image, and the noisy original next to it if requested
+    pub fn denoised_png(&self, denoiser: &Denoiser, keep_noisy: bool) {
+        if keep_noisy {
+            self.save(&self.sampler.samples, Path::new("fractal-noisy.png"));
+        }
+
+        self.save(&denoiser.denoise(&self.sampler.samples), Path::new("fractal.png"));
+    }
+
+    pub fn save(&self, samples: &[Vec<Sample>], path: &Path) {
+        let img = image(samples, self.sampler.cam.width, self.sampler.cam.height);
+
+        let fout = &mut File::create(path).unwrap();
         ImageRgb8(img).save(fout, PNG).unwrap();
     }
 }
 
+pub fn image(samples: &[Vec<Sample>], width: usize, height: usize) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        // Pixels without samples yet are black rather than NaN
+        let count = samples[x][y].count.max(1) as f64;
+
+        Rgb([
+            color(samples[x][y].red / count),
+            color(samples[x][y].green / count),
+            color(samples[x][y].blue / count)
+        ])
+    })
+}
+
 fn color(n: f64) -> u8 {
     gamma(n.min(255.0), 1.0) as u8
 }
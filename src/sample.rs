@@ -1,3 +1,6 @@
+use energy::Energy;
+use direction::Direction;
+
 // Sample could be an aggregate of multiple samples, not neccessarily just one sample
 #[derive(Clone, Debug)]
 pub struct Sample {
@@ -5,4 +8,77 @@ pub struct Sample {
     pub green: f64,
     pub blue: f64,
     pub count: usize,
+    pub square: f64, // Sum of squared luminance, used to estimate the variance
+    pub albedo: Energy, // Sum of first hit albedo, feature buffer for denoising
+    pub normal: Direction, // Sum of first hit normals, feature buffer for denoising
+}
+
+impl Default for Sample {
+    fn default() -> Sample {
+        Sample::new()
+    }
+}
+
+impl Sample {
+    pub fn new() -> Sample {
+        Sample {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+            count: 0,
+            square: 0.0,
+            albedo: Energy { x: 0.0, y: 0.0, z: 0.0 },
+            normal: Direction { x: 0.0, y: 0.0, z: 0.0 },
+        }
+    }
+
+    pub fn add(&mut self, energy: &Energy, albedo: &Energy, normal: &Direction) {
+        let luminance = luminance(energy);
+
+        self.red += energy.x;
+        self.green += energy.y;
+        self.blue += energy.z;
+        self.count += 1;
+        self.square += luminance * luminance;
+        self.albedo = &self.albedo + albedo;
+        self.normal = &self.normal + normal;
+    }
+
+    pub fn merge(&mut self, other: &Sample) {
+        self.red += other.red;
+        self.green += other.green;
+        self.blue += other.blue;
+        self.count += other.count;
+        self.square += other.square;
+        self.albedo = &self.albedo + &other.albedo;
+        self.normal = &self.normal + &other.normal;
+    }
+
+    pub fn mean(&self) -> Energy {
+        if self.count == 0 {
+            return Energy { x: 0.0, y: 0.0, z: 0.0 };
+        }
+
+        let count = self.count as f64;
+        Energy {
+            x: self.red / count,
+            y: self.green / count,
+            z: self.blue / count,
+        }
+    }
+
+    // Variance of the luminance of a single sample
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+
+        let count = self.count as f64;
+        let mean = luminance(&self.mean());
+        ((self.square / count - mean * mean) * count / (count - 1.0)).max(0.0)
+    }
+}
+
+pub fn luminance(e: &Energy) -> f64 {
+    0.2126 * e.x + 0.7152 * e.y + 0.0722 * e.z
 }
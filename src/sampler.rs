@@ -1,85 +1,286 @@
 use camera::Camera;
 use energy::Energy;
-use rand::ThreadRng;
+use direction::Direction;
+use vector3::Vector3;
+use material::Material;
+use surface::{Surface, Geometry};
+use lamp::Lamp;
+use lighttree::{Selection, Emitter};
+use rand::{Rng, XorShiftRng};
 use scene::Scene;
 use ray3::Ray3;
+use differential::Differential;
+use medium::{Medium, walk};
 use std::fmt;
+use std::io;
 use sample::Sample;
+use preview::Preview;
+use std::cell::Cell;
+use std::sync::Arc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SamplerConfiguration {
     pub max_bounces: usize,
     pub adapt: usize,
 }
 
 pub struct Sampler<'a> {
-    config: SamplerConfiguration,
+    pub config: SamplerConfiguration,
     pub samples: Vec<Vec<Sample>>,
-    pub cam: &'a Camera,
-    scene: &'a Scene<'a>,
+    pub cam: &'a Camera<f64>,
+    pub scene: &'a Scene<'a>,
+    pub preview: Option<Arc<Preview>>, // Receives the samples after every pass or tile
+    rays: Cell<usize>,
 }
 
 impl<'a> Sampler<'a> {
-    pub fn new(camera: &'a Camera, scene: &'a Scene, config: SamplerConfiguration) -> Sampler<'a> {
+    pub fn new(camera: &'a Camera<f64>, scene: &'a Scene, config: SamplerConfiguration) -> Sampler<'a> {
         Sampler {
             config: config,
-            samples: vec![vec![Sample { red: 0.0, green: 0.0, blue: 0.0, count: 0 }; camera.height]; camera.width],
+            samples: vec![vec![Sample::new(); camera.height]; camera.width],
             cam: camera,
             scene: scene,
+            preview: None,
+            rays: Cell::new(0),
         }
     }
 
-    pub fn sample_pixel(&mut self, x: usize, y: usize, rng: &mut ThreadRng, samples: usize) {
+    pub fn sample_pixel(&mut self, x: usize, y: usize, rng: &mut XorShiftRng, samples: usize) {
         // println!("self.config.width: {}", self.config.width);
         // println!("self.config.height: {}", self.config.height);
         // println!("x: {} y: {}", x as f64 / self.config.width as f64, y as f64 / self.config.height as f64);
 
         for _ in 0..samples {
-            let sample = self.trace(x as f64 / self.cam.width as f64, y as f64 / self.cam.height as f64, rng);
-            // println!("{:?}", sample);
-            self.samples[x][y] = Sample {
-                red: self.samples[x][y].red + sample.x,
-                green: self.samples[x][y].green + sample.y,
-                blue: self.samples[x][y].blue + sample.z,
-                count: self.samples[x][y].count + 1,
-            };
+            let (energy, albedo, normal) = self.trace(x as f64 / self.cam.width as f64, y as f64 / self.cam.height as f64, rng);
+            // println!("{:?}", energy);
+            self.samples[x][y].add(&energy, &albedo, &normal);
         }
         // println!("{:?}", self.samples[x][y]);
     }
 
-    pub fn trace(&self, x: f64, y: f64, rng: &mut ThreadRng) -> Energy {
+    // Hands the samples to the preview server, called after every pass or tile
+    pub fn publish(&self) -> io::Result<()> {
+        match self.preview {
+            Some(ref preview) => preview.publish(&self.samples, self.rays.replace(0)),
+            None => Ok(()),
+        }
+    }
+
+    // Returns the traced energy together with the albedo and normal of the first hit
+    pub fn trace(&self, x: f64, y: f64, rng: &mut XorShiftRng) -> (Energy, Energy, Direction) {
         let mut ray = self.cam.ray(x, y, rng);
+        let mut differential = Some(Differential::camera(&ray.direction, self.cam.pixel_angle()));
         let mut energy = Energy{x: 0.0, y: 0.0, z: 0.0};
         let mut signal = Energy{x: 1.0, y: 1.0, z: 1.0};
+        let mut albedo = Energy{x: 0.0, y: 0.0, z: 0.0};
+        let mut first = Direction{x: 0.0, y: 0.0, z: 0.0};
+        let mut inside = None;
+        // Density of the last bounce through the lobes `evaluate` covers, zero when the
+        // direct light estimate couldn't have found the same light, as after a bounce
+        // off a singular lobe.
+        let mut last_pdf = 0.0;
 
-        for _ in 0..self.config.max_bounces {
-            if let Some((surface, dist)) = self.scene.intersect(&ray) {
+        for bounce in 0..self.config.max_bounces {
+            // Bounded media take the place of the atmosphere
+            if let Some(medium) = inside.or(self.scene.atmosphere.as_ref()) {
+                let bounded = inside.is_some();
+                let mut scattered = Energy { x: 0.0, y: 0.0, z: 0.0 };
+                let mut last = None;
+                let (reached, rays) = walk(self.scene, medium, &mut ray, &mut signal, &mut energy, rng, &mut |point, dir, signal, rng| {
+                    let lit = self.direct(&Scatter::Medium(medium, bounded), dir, point, rng);
+                    scattered = scattered.merged(&lit, signal);
+                    last = Some(dir.clone());
+                });
+                self.rays.set(self.rays.get() + rays);
+                energy = &energy + &scattered;
+                if !reached {
+                    return (energy, albedo, first);
+                }
+                if let Some(dir) = last {
+                    differential = None;
+                    last_pdf = medium.phase(&dir, &ray.direction);
+                }
+            }
+
+            self.rays.set(self.rays.get() + 1);
+            if let Some((index, dist)) = self.scene.hit(&ray) {
+                let surface = &self.scene.surfaces()[index];
                 let point = ray.moved(dist);
-                let (normal, mat) = surface.at(&point);
-                energy = energy.merged(&mat.emit(&normal, &ray.direction), &signal);
+                let (mut geometry, mat) = surface.at(&point);
+                let transferred = differential.map(|d| d.transfer(&ray.direction, dist, &geometry.geometric));
+                if let Some(ref d) = transferred {
+                    geometry.footprint = d.footprint(&geometry);
+                }
+                if bounce == 0 {
+                    albedo = mat.albedo(&geometry);
+                    first = geometry.normal.clone();
+                }
+                energy = energy.merged(&self.emitted(surface.as_ref(), index, &geometry, mat, &ray, dist, last_pdf), &signal);
+                energy = energy.merged(&self.direct(&Scatter::Surface(&geometry, mat), &ray.direction, &point, rng), &signal);
 
                 if let Some(newsignal) = signal.random_gain(rng) {
                     signal = newsignal;
                 } else {
-                    return energy;
+                    return (energy, albedo, first);
                 }
 
-                if let (true, direction, strength) = mat.bsdf(&normal, &ray.direction, dist, rng) {
+                if let (true, direction, strength, singular) = mat.sample(&geometry, &ray.direction, dist, rng) {
                     signal = &signal * &strength;
+                    differential = transferred.and_then(|d| d.scattered(&ray.direction, &direction, &geometry.geometric));
+                    inside = if direction.dot(&geometry.geometric) < 0.0 { mat.medium() } else { None };
+                    last_pdf = if singular { 0.0 } else { mat.pdf(&geometry, &ray.direction, &direction) };
                     ray = Ray3 {
                         origin: point,
                         direction: direction,
                     }
                 } else {
-                    return energy;
+                    return (energy, albedo, first);
                 }
             } else {
-                return energy.merged(&self.scene.env(&ray), &signal);
+                let weight = match self.scene.environment {
+                    Some(ref environment) if last_pdf > 0.0 => power_heuristic(last_pdf, environment.pdf(&ray.direction)),
+                    _ => 1.0,
+                };
+                return (energy.merged(&self.scene.env(&ray).amplified(weight), &signal), albedo, first);
+            }
+        }
+
+        (energy, albedo, first)
+    }
+
+    // Light arriving straight from the lamps of the scene and scattered towards `inc`
+    fn direct(&self, scatter: &Scatter, inc: &Direction, point: &Vector3, rng: &mut XorShiftRng) -> Energy {
+        let mut total = Energy { x: 0.0, y: 0.0, z: 0.0 };
+        let lamps = &self.scene.lamps;
+
+        match self.scene.selection {
+            Selection::All => {
+                for lamp in lamps.iter() {
+                    total = &total + &self.lamp(lamp, scatter, inc, point, rng);
+                }
+            }
+            ref selection => {
+                for lamp in lamps.iter().filter(|l| !l.local()) {
+                    total = &total + &self.lamp(lamp, scatter, inc, point, rng);
+                }
+                match selection.choose(point, rng.gen_range(0.0, 1.0)) {
+                    Some((Emitter::Lamp(i), chance)) => {
+                        total = &total + &self.lamp(&lamps[i], scatter, inc, point, rng).amplified(1.0 / chance);
+                    }
+                    Some((Emitter::Surface(i), chance)) => {
+                        total = &total + &self.surface(self.scene.surfaces()[i].as_ref(), chance, scatter, inc, point, rng);
+                    }
+                    None => {}
+                }
             }
         }
 
-        energy
+        // The environment, weighted against the chance of the material sampling the same direction
+        if let Some(ref environment) = self.scene.environment {
+            let (to, radiance, pdf) = environment.sample(rng);
+            let f = scatter.evaluate(inc, &to);
+            if pdf > 0.0 && f.max() > 0.0 && radiance.max() > 0.0 {
+                self.rays.set(self.rays.get() + 1);
+                let tr = self.scene.transmittance(point, &to, f64::INFINITY, scatter.medium(&to), rng);
+                let weight = power_heuristic(pdf, scatter.pdf(inc, &to)) / pdf;
+                total = total.merged(&f, &(&radiance * &tr).amplified(weight));
+            }
+        }
+
+        total
+    }
+
+    fn lamp(&self, lamp: &Lamp, scatter: &Scatter, inc: &Direction, point: &Vector3, rng: &mut XorShiftRng) -> Energy {
+        let (to, dist, irradiance) = lamp.sample(point, rng);
+        let f = scatter.evaluate(inc, &to);
+        if f.max() <= 0.0 || irradiance.max() <= 0.0 {
+            return Energy { x: 0.0, y: 0.0, z: 0.0 };
+        }
+
+        self.rays.set(self.rays.get() + 1);
+        let tr = self.scene.transmittance(point, &to, dist, scatter.medium(&to), rng);
+        &f * &(&irradiance * &tr)
+    }
+
+    // Light from a point picked on an emissive surface, weighted against the
+    // chance of the material sampling the same direction
+    fn surface(&self, surface: &Surface, chance: f64, scatter: &Scatter, inc: &Direction, point: &Vector3, rng: &mut XorShiftRng) -> Energy {
+        let (target, density) = surface.sample(rng);
+        let offset = &target - point;
+        let dist = offset.len();
+        let to = offset.unit();
+        let (geometry, mat) = surface.at(&target);
+        let cos = geometry.geometric.dot(&to).abs();
+        let radiance = mat.emit(&geometry, &to);
+        let f = scatter.evaluate(inc, &to);
+        if dist <= 0.0 || cos <= 0.0 || f.max() <= 0.0 || radiance.max() <= 0.0 {
+            return Energy { x: 0.0, y: 0.0, z: 0.0 };
+        }
+
+        self.rays.set(self.rays.get() + 1);
+        let tr = self.scene.transmittance(point, &to, dist, scatter.medium(&to), rng);
+        let pdf = chance * density * dist * dist / cos;
+        let weight = power_heuristic(pdf, scatter.pdf(inc, &to)) / pdf;
+        &f * &(&radiance * &tr).amplified(weight)
     }
+
+    // Light emitted by a surface a ray hit, weighted against the chance of the
+    // direct light estimate at the start of the ray picking the same point
+    #[allow(clippy::too_many_arguments)]
+    fn emitted(&self, surface: &Surface, index: usize, geometry: &Geometry, mat: &Material, ray: &Ray3, dist: f64, last_pdf: f64) -> Energy {
+        let emitted = mat.emit(geometry, &ray.direction);
+        if last_pdf <= 0.0 || emitted.max() <= 0.0 {
+            return emitted;
+        }
+
+        let chance = self.scene.selection.chance(&ray.origin, Emitter::Surface(index));
+        let cos = geometry.geometric.dot(&ray.direction).abs();
+        if chance <= 0.0 || cos <= 0.0 {
+            return emitted;
+        }
+
+        let pdf = chance * surface.pdf(&ray.moved(dist)) * dist * dist / cos;
+        emitted.amplified(power_heuristic(last_pdf, pdf))
+    }
+}
+
+// Where light from the lamps is scattered towards the camera
+enum Scatter<'b> {
+    Surface(&'b Geometry, &'b Material),
+    Medium(&'b Medium, bool), // Whether bounded by a surface rather than the atmosphere
+}
+
+impl<'b> Scatter<'b> {
+    // The bsdf times the cosine to the normal, or the phase function
+    fn evaluate(&self, inc: &Direction, out: &Direction) -> Energy {
+        match *self {
+            Scatter::Surface(g, mat) => mat.evaluate(g, inc, out).amplified(g.normal.dot(out).abs()),
+            Scatter::Medium(medium, _) => {
+                let p = medium.phase(inc, out);
+                Energy { x: p, y: p, z: p }
+            }
+        }
+    }
+
+    fn pdf(&self, inc: &Direction, out: &Direction) -> f64 {
+        match *self {
+            Scatter::Surface(g, mat) => mat.pdf(g, inc, out),
+            Scatter::Medium(medium, _) => medium.phase(inc, out),
+        }
+    }
+
+    // The medium a shadow ray towards `out` starts in, none for the atmosphere
+    fn medium(&self, out: &Direction) -> Option<&'b Medium> {
+        match *self {
+            Scatter::Surface(g, mat) => if out.dot(&g.geometric) < 0.0 { mat.medium() } else { None },
+            Scatter::Medium(medium, bounded) => if bounded { Some(medium) } else { None },
+        }
+    }
+}
+
+// Multiple importance sampling weight of a strategy with density `a` against one with `b`
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    a * a / (a * a + b * b)
 }
 
 impl<'a> fmt::Debug for Sampler<'a> {
@@ -87,3 +288,88 @@ impl<'a> fmt::Debug for Sampler<'a> {
         write!(f, "Sampler {{ config: {:?}, cam: {:?}, scene: {:?} }}", self.config, self.cam, self.scene)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Sampler, SamplerConfiguration};
+    use camera::Camera;
+    use scene::Scene;
+    use quad::Quad;
+    use material::{Material, Plastic, Lambert, Light};
+    use sphere::Sphere;
+    use matrix4::Matrix4;
+    use lighttree::Selection;
+    use surface::{Surface, Geometry};
+    use environment::Environment;
+    use energy::Energy;
+    use direction::Direction;
+    use rand::{SeedableRng, XorShiftRng};
+
+    #[test]
+    fn environment_mis() {
+        // A plane facing the camera, lit by a sky with a sun
+        let (width, height) = (32, 16);
+        let pixels = (0..width * height)
+            .map(|i| if i == 4 * width + 5 { Energy { x: 200.0, y: 200.0, z: 200.0 } } else { Energy { x: 1.0, y: 1.0, z: 1.0 } })
+            .collect();
+        let plastic = Plastic::new(0.8, 0.5, 0.2, 0.5);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(&plastic))];
+        let mut scene = Scene::new(&surfaces);
+        scene.environment = Some(Environment::new(width, height, pixels));
+        let camera = Camera::new(16, 16, 0.050, 0.024, 4.0);
+        let sampler = Sampler::new(&camera, &scene, SamplerConfiguration { max_bounces: 2, adapt: 0 });
+        let mut rng = XorShiftRng::from_seed([4, 7, 4, 7]);
+
+        let n = 100000;
+        let mut mis = Energy { x: 0.0, y: 0.0, z: 0.0 };
+        for _ in 0..n {
+            mis = &mis + &sampler.trace(0.5, 0.5, &mut rng).0;
+        }
+
+        // Only following the bsdf into the environment
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.5, 0.5);
+        let inc = Direction { x: 0.0, y: 0.0, z: -1.0 };
+        let environment = scene.environment.as_ref().unwrap();
+        let mut reference = Energy { x: 0.0, y: 0.0, z: 0.0 };
+        for _ in 0..n {
+            if let (true, out, strength, _) = plastic.sample(&g, &inc, 1.0, &mut rng) {
+                reference = reference.merged(&environment.radiance(&out), &strength);
+            }
+        }
+
+        for &(a, b) in &[(mis.x, reference.x), (mis.y, reference.y), (mis.z, reference.z)] {
+            assert!((a - b).abs() < 0.03 * b, "{:?} {:?}", mis.amplified(1.0 / n as f64), reference.amplified(1.0 / n as f64));
+        }
+    }
+
+    #[test]
+    fn emissive_mis() {
+        // A plane facing the camera, lit by a glowing ball beside the view
+        let lambert = Lambert::new(0.8, 0.5, 0.2);
+        let light = Light::new(2000.0, 2000.0, 2000.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(&lambert)),
+                                               Box::new(Sphere::new(&light).placed(Matrix4::translation(1.0, 0.0, 0.5)))];
+        let mut scene = Scene::new(&surfaces);
+        let camera = Camera::new(16, 16, 0.050, 0.024, 4.0);
+        let mut rng = XorShiftRng::from_seed([2, 7, 1, 8]);
+
+        // Hitting the ball only against also picking points on it
+        let n = 200000;
+        let mut totals = Vec::new();
+        let selections = [Selection::All, Selection::tree(&scene)];
+        for selection in selections.iter() {
+            scene.selection = selection.clone();
+            let sampler = Sampler::new(&camera, &scene, SamplerConfiguration { max_bounces: 2, adapt: 0 });
+            let mut total = Energy { x: 0.0, y: 0.0, z: 0.0 };
+            for _ in 0..n {
+                total = &total + &sampler.trace(0.5, 0.5, &mut rng).0;
+            }
+            totals.push(total);
+        }
+
+        let (hit, mis) = (&totals[0], &totals[1]);
+        for &(a, b) in &[(mis.x, hit.x), (mis.y, hit.y), (mis.z, hit.z)] {
+            assert!((a - b).abs() < 0.03 * b, "{:?} {:?}", mis.amplified(1.0 / n as f64), hit.amplified(1.0 / n as f64));
+        }
+    }
+}
@@ -1,39 +1,377 @@
 use surface::Surface;
-use std::f64::INFINITY;
 use ray3::Ray3;
-use constants::UP;
 use energy::Energy;
+use encode::fnv;
+use vector3::Vector3;
+use direction::Direction;
+use lamp::Lamp;
+use lighttree::Selection;
+use environment::Environment;
+use medium::Medium;
+use constants::{BIAS, UP};
+use rand::XorShiftRng;
+
+// Cutouts a ray passes through before it is considered blocked
+const MAX_CUTOUTS: usize = 64;
 
 #[derive(Debug)]
 pub struct Scene<'a> {
     surfaces: &'a Vec<Box<Surface + 'a>>,
+    pub lamps: Vec<Lamp>,
+    pub selection: Selection, // Rebuild after changing the lamps
+    pub environment: Option<Environment>, // Replaces the default sky gradient
+    // Fills the space outside bounded media up to the surfaces. Rays that hit
+    // nothing leave it, so the environment and distant lamps light it from outside.
+    pub atmosphere: Option<Medium>,
 }
 
 impl<'a> Scene<'a> {
     pub fn new(surfaces: &'a Vec<Box<Surface + 'a>>) -> Scene<'a> {
         Scene {
             surfaces: surfaces,
+            lamps: Vec::new(),
+            selection: Selection::All,
+            environment: None,
+            atmosphere: None,
+        }
+    }
+
+    // Surfaces are named by their position here, as by the light selection
+    pub fn surfaces(&self) -> &'a [Box<Surface + 'a>] {
+        self.surfaces
+    }
+
+    // The closest hit on a surface that isn't cut out by the opacity of its
+    // material. Partially opaque hits are kept with a chance of their opacity.
+    pub fn intersect(&self, ray: &Ray3) -> Option<(&(Surface + 'a), f64)> {
+        self.hit(ray).map(|(i, dist)| (&*self.surfaces[i], dist))
+    }
+
+    // The index of the surface `intersect` hits and the distance to it
+    pub fn hit(&self, ray: &Ray3) -> Option<(usize, f64)> {
+        let mut travelled = 0.0;
+        let mut origin = ray.origin.clone();
+
+        for _ in 0..MAX_CUTOUTS {
+            let current = Ray3 {
+                origin: origin,
+                direction: ray.direction.clone(),
+            };
+            let (i, dist) = self.closest(&current)?;
+            let surface = &self.surfaces[i];
+
+            if !surface.material().masked() {
+                return Some((i, travelled + dist));
+            }
+
+            let point = current.moved(dist);
+            let (geometry, mat) = surface.at(&point);
+            let opacity = mat.opacity(&geometry);
+            if opacity >= 1.0 || (opacity > 0.0 && hashed(&point, &ray.direction) < opacity) {
+                return Some((i, travelled + dist));
+            }
+
+            travelled += dist;
+            origin = point;
+        }
+
+        None
+    }
+
+    // Whether nothing blocks the way from `point` along `dir` for `dist`
+    pub fn visible(&self, point: &Vector3, dir: &Direction, dist: f64) -> bool {
+        let ray = Ray3 {
+            origin: point.clone(),
+            direction: dir.clone(),
+        };
+
+        match self.intersect(&ray) {
+            Some((_, d)) => d >= dist - BIAS,
+            None => true,
+        }
+    }
+
+    // Light left after travelling from `point` along `dir` for `dist`, starting
+    // in the medium `inside` or the atmosphere. Shadow rays pass through the
+    // transparent bounds of media and are blocked by any other surface.
+    pub fn transmittance(&self, point: &Vector3, dir: &Direction, dist: f64, inside: Option<&Medium>, rng: &mut XorShiftRng) -> Energy {
+        let mut tr = Energy { x: 1.0, y: 1.0, z: 1.0 };
+        let mut inside = inside;
+        let mut ray = Ray3 {
+            origin: point.clone(),
+            direction: dir.clone(),
+        };
+        let mut left = dist;
+
+        for _ in 0..MAX_CUTOUTS {
+            let hit = match self.intersect(&ray) {
+                Some((surface, d)) if d < left - BIAS => Some((surface, d)),
+                _ => None,
+            };
+            let segment = hit.as_ref().map_or(left, |&(_, d)| d);
+            if segment < f64::INFINITY {
+                if let Some(medium) = inside.or(self.atmosphere.as_ref()) {
+                    tr = &tr * &medium.transmittance_along(&ray, segment, rng);
+                }
+            }
+
+            let (surface, d) = match hit {
+                Some(hit) => hit,
+                None => return tr,
+            };
+            let point = ray.moved(d);
+            let (geometry, mat) = surface.at(&point);
+            if !mat.transparent() {
+                return Energy { x: 0.0, y: 0.0, z: 0.0 };
+            }
+
+            inside = if dir.dot(&geometry.geometric) < 0.0 { mat.medium() } else { None };
+            ray.origin = point;
+            left -= d;
         }
+
+        Energy { x: 0.0, y: 0.0, z: 0.0 }
     }
 
-    pub fn intersect(&self, ray: &Ray3) -> Option<(&Box<Surface + 'a>, f64)> {
-        let dist = INFINITY;
+    fn closest(&self, ray: &Ray3) -> Option<(usize, f64)> {
+        let mut dist = f64::INFINITY;
         let mut result = None;
 
-        for surface in self.surfaces.iter() {
+        for (n, surface) in self.surfaces.iter().enumerate() {
             let (i, d) = surface.intersect(ray);
 
             if i && d < dist {
-                result = Some((surface, dist))
+                dist = d;
+                result = Some((n, dist))
             }
         }
 
         result
     }
 
+    // Changes whenever a parameter of the scene changes, including how lights
+    // are selected. Images, grids and measured data only add the digest taken
+    // when they were loaded.
+    pub fn hash(&self) -> u64 {
+        let mut bytes = Vec::new();
+        for surface in self.surfaces.iter() {
+            bytes.extend_from_slice(&surface.digest().to_le_bytes());
+        }
+
+        for lamp in &self.lamps {
+            match *lamp {
+                Lamp::Point { ref position, ref intensity } => {
+                    put(&mut bytes, &[0.0, position.x, position.y, position.z, intensity.x, intensity.y, intensity.z])
+                }
+                Lamp::Spot { ref position, ref axis, ref intensity, cone, falloff } => {
+                    put(&mut bytes, &[1.0, position.x, position.y, position.z, axis.x, axis.y, axis.z]);
+                    put(&mut bytes, &[intensity.x, intensity.y, intensity.z, cone, falloff])
+                }
+                Lamp::Distant { ref toward, ref irradiance, angle } => {
+                    put(&mut bytes, &[2.0, toward.x, toward.y, toward.z, irradiance.x, irradiance.y, irradiance.z, angle])
+                }
+            }
+        }
+
+        if let Some(ref e) = self.environment {
+            put(&mut bytes, &[3.0, e.width as f64, e.height as f64, e.rotation, e.intensity]);
+            bytes.extend_from_slice(&e.digest().to_le_bytes());
+        }
+
+        if let Some(ref m) = self.atmosphere {
+            put(&mut bytes, &[4.0]);
+            bytes.extend_from_slice(&m.digest().to_le_bytes());
+        }
+
+        let selection = match self.selection {
+            Selection::All => 0.0,
+            Selection::Uniform(_) => 1.0,
+            Selection::Power(..) => 2.0,
+            Selection::Tree(_) => 3.0,
+        };
+        put(&mut bytes, &[5.0, selection]);
+
+        fnv(&bytes)
+    }
+
     pub fn env(&self, ray: &Ray3) -> Energy {
+        if let Some(ref environment) = self.environment {
+            return environment.radiance(&ray.direction);
+        }
+
         let vertical = ((ray.direction.dot(&UP) + 0.5) / 1.5).max(0.0);
 
         Energy {x: 0.0, y: 0.0, z: 0.0}.lerp(&Energy {x: 255.0, y: 255.0, z: 255.0}, vertical)
     }
 }
+
+fn put(bytes: &mut Vec<u8>, values: &[f64]) {
+    for n in values {
+        bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+    }
+}
+
+// Uniform number in [0, 1) for a point hit from a direction, so the same ray
+// always passes or hits a partially opaque surface
+fn hashed(point: &Vector3, dir: &Direction) -> f64 {
+    let mut bytes = Vec::with_capacity(48);
+    put(&mut bytes, &[point.x, point.y, point.z, dir.x, dir.y, dir.z]);
+
+    (fnv(&bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scene;
+    use quad::Quad;
+    use material::{Material, Lambert};
+    use surface::{Surface, Geometry};
+    use ray3::Ray3;
+    use vector3::Vector3;
+    use direction::Direction;
+    use energy::Energy;
+    use lamp::Lamp;
+    use environment::Environment;
+    use lighttree::Selection;
+    use encode::Digest;
+    use rand::XorShiftRng;
+
+    // Plane across the z axis at `z`, hit by rays going up it
+    #[derive(Debug)]
+    struct Wall<'a> {
+        z: f64,
+        material: &'a Material,
+    }
+
+    impl<'a> Surface for Wall<'a> {
+        fn intersect(&self, r: &Ray3) -> (bool, f64) {
+            let dist = (self.z - r.origin.z) / r.direction.z;
+            (dist > 0.0, dist)
+        }
+
+        fn at(&self, _: &Vector3) -> (Geometry, &Material) {
+            (Geometry::new(&Direction { x: 0.0, y: 0.0, z: -1.0 }, 0.0, 0.0), self.material)
+        }
+
+        fn material(&self) -> &Material {
+            self.material
+        }
+
+        fn bounds(&self) -> (Vector3, Vector3) {
+            let inf = f64::INFINITY;
+            (Vector3 { x: -inf, y: -inf, z: self.z }, Vector3 { x: inf, y: inf, z: self.z })
+        }
+
+        fn area(&self) -> f64 {
+            f64::INFINITY
+        }
+
+        fn sample(&self, _: &mut XorShiftRng) -> (Vector3, f64) {
+            unreachable!("the wall doesn't emit")
+        }
+
+        fn pdf(&self, _: &Vector3) -> f64 {
+            0.0
+        }
+
+        fn digest(&self) -> u64 {
+            Digest::new("Wall").value(self.z).with(self.material.digest()).finish()
+        }
+    }
+
+    #[test]
+    fn closest_hit() {
+        // The nearer wall is listed first, so the hit must not just be the last one
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Wall { z: 0.0, material: &lambert }),
+                                               Box::new(Wall { z: 2.0, material: &lambert })];
+        let scene = Scene::new(&surfaces);
+        let ray = Ray3 {
+            origin: Vector3 { x: 0.0, y: 0.0, z: -3.0 },
+            direction: Direction { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let (_, dist) = scene.intersect(&ray).unwrap();
+        assert!((dist - 3.0).abs() < 1e-9, "{}", dist);
+    }
+
+    // Only answers intersections, the geometry must not be asked for
+    #[derive(Debug)]
+    struct Opaque<'a> {
+        material: &'a Material,
+    }
+
+    impl<'a> Surface for Opaque<'a> {
+        fn intersect(&self, r: &Ray3) -> (bool, f64) {
+            (r.direction.z > 0.0, -r.origin.z)
+        }
+
+        fn at(&self, _: &Vector3) -> (Geometry, &Material) {
+            panic!("opaque hits don't need the geometry")
+        }
+
+        fn material(&self) -> &Material {
+            self.material
+        }
+
+        fn bounds(&self) -> (Vector3, Vector3) {
+            let inf = f64::INFINITY;
+            (Vector3 { x: -inf, y: -inf, z: 0.0 }, Vector3 { x: inf, y: inf, z: 0.0 })
+        }
+
+        fn area(&self) -> f64 {
+            f64::INFINITY
+        }
+
+        fn sample(&self, _: &mut XorShiftRng) -> (Vector3, f64) {
+            unreachable!("the test surface doesn't emit")
+        }
+
+        fn pdf(&self, _: &Vector3) -> f64 {
+            0.0
+        }
+
+        fn digest(&self) -> u64 {
+            Digest::new("Opaque").with(self.material.digest()).finish()
+        }
+    }
+
+    #[test]
+    fn opaque_hit() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Opaque { material: &lambert })];
+        let scene = Scene::new(&surfaces);
+        let ray = Ray3 {
+            origin: Vector3 { x: 0.0, y: 0.0, z: -2.0 },
+            direction: Direction { x: 0.0, y: 0.0, z: 1.0 },
+        };
+        let (_, dist) = scene.intersect(&ray).unwrap();
+        assert_eq!(dist, 2.0);
+    }
+
+    #[test]
+    fn scene_hash() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Quad::new(&lambert))];
+        let mut scene = Scene::new(&surfaces);
+        let plain = scene.hash();
+        assert_eq!(plain, Scene::new(&surfaces).hash());
+
+        scene.lamps.push(Lamp::point(Vector3 { x: 0.0, y: 1.0, z: 0.0 }, Energy { x: 1.0, y: 1.0, z: 1.0 }));
+        let lit = scene.hash();
+        assert!(lit != plain);
+
+        scene.environment = Some(Environment::new(2, 1, vec![Energy { x: 1.0, y: 1.0, z: 1.0 }; 2]));
+        let sky = scene.hash();
+        scene.environment.as_mut().unwrap().intensity = 2.0;
+        let brighter = scene.hash();
+        assert!(sky != lit && brighter != sky);
+
+        scene.selection = Selection::tree(&scene);
+        assert!(scene.hash() != brighter);
+
+        // Parameters of materials count, not just the kind of material
+        let rough = Lambert::rough(1.0, 1.0, 1.0, 0.5);
+        let other: Vec<Box<Surface>> = vec![Box::new(Quad::new(&rough))];
+        assert!(Scene::new(&other).hash() != plain);
+    }
+}
@@ -0,0 +1,209 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, pass, black};
+use medium::Medium;
+use microfacet::{Frame, charlie, velvet_visibility, cosine_hemisphere};
+use surface::Geometry;
+use texture::Texture;
+use rand::{Rng, XorShiftRng};
+use std::f64::consts::PI;
+
+// Directional albedo of a white sheen lobe, by roughness in rows from 0 to 1
+// and the cosine to the viewer in columns from 0 to 1
+const ALBEDO: [[f64; 8]; 8] = [[1.324, 0.676, 0.340, 0.164, 0.072, 0.026, 0.007, 0.000],
+                               [1.324, 0.676, 0.340, 0.164, 0.072, 0.026, 0.007, 0.000],
+                               [1.249, 0.667, 0.353, 0.182, 0.086, 0.035, 0.010, 0.001],
+                               [0.929, 0.597, 0.392, 0.257, 0.163, 0.095, 0.048, 0.016],
+                               [0.770, 0.542, 0.394, 0.289, 0.209, 0.144, 0.092, 0.049],
+                               [0.681, 0.504, 0.388, 0.303, 0.236, 0.179, 0.129, 0.085],
+                               [0.625, 0.478, 0.381, 0.310, 0.252, 0.202, 0.158, 0.116],
+                               [0.588, 0.460, 0.376, 0.314, 0.263, 0.219, 0.179, 0.141]];
+
+// Velvet and fabric. Fibers standing out of the surface scatter light at grazing
+// angles, which gives cloth its soft rim. Either on its own or added on top of
+// another material, such as a `Lambert` of the fabric color.
+#[derive(Debug)]
+pub struct Sheen<'a> {
+    pub base: Option<&'a Material>,
+    pub color: Texture,
+    pub roughness: Texture,
+}
+
+impl<'a> Sheen<'a> {
+    pub fn new(r: f64, g: f64, b: f64, roughness: f64) -> Sheen<'a> {
+        Sheen {
+            base: None,
+            color: Texture::constant(r, g, b),
+            roughness: Texture::value(roughness),
+        }
+    }
+
+    // Sheen added to the reflection of `base`
+    pub fn over(base: &'a Material, r: f64, g: f64, b: f64, roughness: f64) -> Sheen<'a> {
+        Sheen {
+            base: Some(base),
+            color: Texture::constant(r, g, b),
+            roughness: Texture::value(roughness),
+        }
+    }
+
+    // Chance of sampling the sheen rather than the base
+    fn chance(&self) -> f64 {
+        if self.base.is_some() { 0.5 } else { 1.0 }
+    }
+
+    // Share of the light left for the base after the sheen took its part, as
+    // in the Charlie sheen of Estevez and Kulla. `cos` is towards the viewer.
+    fn base_scale(&self, g: &Geometry, cos: f64) -> f64 {
+        let x = cos.clamp(0.0, 1.0) * 7.0;
+        let y = self.roughness.filtered_scalar(g).clamp(0.0, 1.0) * 7.0;
+        let (i, j) = ((y as usize).min(6), (x as usize).min(6));
+        let (ty, tx) = (y - i as f64, x - j as f64);
+        let row = |i: usize| ALBEDO[i][j] + (ALBEDO[i][j + 1] - ALBEDO[i][j]) * tx;
+        let albedo = row(i) + (row(i + 1) - row(i)) * ty;
+
+        (1.0 - self.color.filtered(g).max() * albedo).max(0.0)
+    }
+
+    // The sheen lobe alone, in local coordinates
+    fn lobe(&self, g: &Geometry, wo: &Direction, wi: &Direction) -> Energy {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return black();
+        }
+
+        let roughness = self.roughness.filtered_scalar(g);
+        let h = (wo + wi).unit();
+        self.color.filtered(g).amplified(charlie(h.z, roughness * roughness) * velvet_visibility(wo.z, wi.z))
+    }
+}
+
+impl<'a> Material for Sheen<'a> {
+    fn sample(&self, g: &Geometry, inc: &Direction, dist: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        if !inc.enters(&g.normal) {
+            return match self.base {
+                Some(base) => base.sample(g, inc, dist, rng),
+                None => pass(inc),
+            };
+        }
+
+        let chance = self.chance();
+        if rng.gen_range(0.0, 1.0) >= chance {
+            let base = self.base.unwrap();
+            let (ok, out, strength, singular) = base.sample(g, inc, dist, rng);
+            let scale = self.base_scale(g, -g.normal.cos(inc));
+            return (ok, out, strength.amplified(scale / (1.0 - chance)), singular);
+        }
+
+        // The lobe is wide, cosine weighting is close enough
+        let frame = Frame::new(&g.normal);
+        let wo = frame.local(&inc.invert());
+        let wi = cosine_hemisphere(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0));
+        let strength = self.lobe(g, &wo, &wi).amplified(PI / chance);
+        (true, frame.world(&wi), strength, false)
+    }
+
+    fn evaluate(&self, g: &Geometry, inc: &Direction, out: &Direction) -> Energy {
+        let frame = Frame::new(&g.normal);
+        let sheen = self.lobe(g, &frame.local(&inc.invert()), &frame.local(out));
+        match self.base {
+            Some(base) if inc.enters(&g.normal) => {
+                let scale = self.base_scale(g, -g.normal.cos(inc));
+                &sheen + &base.evaluate(g, inc, out).amplified(scale)
+            }
+            Some(base) => base.evaluate(g, inc, out),
+            None => sheen,
+        }
+    }
+
+    fn pdf(&self, g: &Geometry, inc: &Direction, out: &Direction) -> f64 {
+        let cos = g.normal.dot(out);
+        let sheen = if cos > 0.0 && inc.enters(&g.normal) { cos / PI } else { 0.0 };
+        let chance = self.chance();
+        match self.base {
+            Some(base) => chance * sheen + (1.0 - chance) * base.pdf(g, inc, out),
+            None => sheen,
+        }
+    }
+
+    fn emit(&self, g: &Geometry, dir: &Direction) -> Energy {
+        self.base.map_or(black(), |base| base.emit(g, dir))
+    }
+
+    fn power(&self) -> f64 {
+        self.base.map_or(0.0, |base| base.power())
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.base.and_then(|base| base.medium())
+    }
+
+    fn albedo(&self, g: &Geometry) -> Energy {
+        match self.base {
+            Some(base) => base.albedo(g),
+            None => self.color.filtered(g),
+        }
+    }
+
+    fn opacity(&self, g: &Geometry) -> f64 {
+        self.base.map_or(1.0, |base| base.opacity(g))
+    }
+
+    fn masked(&self) -> bool {
+        self.base.is_some_and(|base| base.masked())
+    }
+
+    fn transparent(&self) -> bool {
+        self.base.is_some_and(|base| base.transparent())
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Sheen").with(self.base.map_or(0, |b| b.digest())).with(self.color.digest()).with(self.roughness.digest()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sheen;
+    use material::{Material, Lambert};
+    use surface::Geometry;
+    use direction::Direction;
+    use furnace;
+    use rand::{SeedableRng, XorShiftRng};
+
+    fn albedo(m: &Material, inc: &Direction) -> f64 {
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        furnace::albedo(m, &g, inc, 50000, &mut XorShiftRng::from_seed([4, 3, 2, 1])).average()
+    }
+
+    #[test]
+    fn sheen_grazing() {
+        let g = Geometry::new(&Direction { x: 0.0, y: 0.0, z: 1.0 }, 0.0, 0.0);
+        let velvet = Sheen::new(1.0, 1.0, 1.0, 0.5);
+
+        // Brighter when seen and lit at grazing angles
+        let top = Direction { x: 0.0, y: 0.0, z: -1.0 };
+        let grazing = Direction { x: 0.95, y: 0.0, z: -0.312 }.unit();
+        let normal = velvet.evaluate(&g, &top, &top.invert()).x;
+        let rim = velvet.evaluate(&g, &grazing, &Direction { x: -0.95, y: 0.0, z: 0.312 }.unit()).x;
+        assert!(rim > 2.0 * normal);
+
+        for inc in &[top.clone(), grazing.clone()] {
+            let a = albedo(&velvet, inc);
+            assert!(a > 0.0 && a < 1.0, "albedo {}", a);
+        }
+
+        // Added on top of a base, which keeps its own reflection
+        let cloth = Lambert::new(0.5, 0.1, 0.1);
+        let fabric = Sheen::over(&cloth, 0.3, 0.3, 0.3, 0.5);
+        assert!(albedo(&fabric, &grazing) > albedo(&cloth, &grazing));
+
+        // The base only gets the light the sheen leaves, so the sum stays below one
+        let pale = Lambert::new(0.8, 0.8, 0.8);
+        let glossy = Sheen::over(&pale, 1.0, 1.0, 1.0, 0.3);
+        for inc in &[top, grazing, Direction { x: 0.995, y: 0.0, z: -0.1 }.unit()] {
+            let a = albedo(&glossy, inc);
+            assert!(a <= 1.0, "albedo {}", a);
+        }
+    }
+}
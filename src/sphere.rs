@@ -1,9 +1,13 @@
 use material::Material;
 use matrix4::Matrix4;
+use encode::Digest;
 use vector3::Vector3;
-use surface::Surface;
+use surface::{Surface, Geometry};
 use ray3::Ray3;
 use constants::BIAS;
+use microfacet::Frame;
+use rand::{Rng, XorShiftRng};
+use std::f64::consts::PI;
 
 #[derive(Debug)]
 pub struct Sphere<'a> {
@@ -18,6 +22,17 @@ impl<'a> Sphere<'a> {
             pos: Matrix4::identity(),
         }
     }
+
+    pub fn placed(mut self, pos: Matrix4) -> Sphere<'a> {
+        self.pos = pos;
+        self
+    }
+
+    // Change of area from the untransformed sphere around the normal `n` there
+    fn stretch(&self, n: &Vector3) -> f64 {
+        let frame = Frame::new(n);
+        self.pos.mult_dist(&frame.tangent).cross(&self.pos.mult_dist(&frame.bitangent)).len()
+    }
 }
 
 impl<'a> Surface for Sphere<'a> {
@@ -52,10 +67,131 @@ impl<'a> Surface for Sphere<'a> {
         (false, 0.0)
     }
 
-    fn at(&self, v: &Vector3) -> (Vector3, &Material) {
+    // Longitude and latitude of the untransformed sphere, v increases along y.
+    // Seen from outside u runs from right to left, so the tangent frame is mirrored.
+    fn at(&self, v: &Vector3) -> (Geometry, &Material) {
         let i = self.pos.inverse();
-        let p = i.mult_point(v);
+        let p = i.mult_point(v).unit();
+        let normal = self.pos.mult_dir(&p);
+
+        let u = 0.5 + p.z.atan2(p.x) / (2.0 * PI);
+        let v = 0.5 + p.y.clamp(-1.0, 1.0).asin() / PI;
+
+        // The poles have no longitude to follow
+        let r = (p.x * p.x + p.z * p.z).sqrt();
+        if r == 0.0 {
+            return (Geometry::new(&normal, u, v), self.material);
+        }
+
+        let dpdu = self.pos.mult_dist(&Vector3 { x: -PI * p.z, y: 0.0, z: PI * p.x });
+        let dpdv = self.pos.mult_dist(&Vector3 {
+            x: -PI / 2.0 * p.y * p.x / r,
+            y: PI / 2.0 * r,
+            z: -PI / 2.0 * p.y * p.z / r,
+        });
+        let tangent = dpdu.unit();
+        let geometry = Geometry {
+            bitangent: tangent.cross(&normal),
+            tangent: tangent,
+            geometric: normal.clone(),
+            normal: normal,
+            u: u,
+            v: v,
+            dpdu: dpdu,
+            dpdv: dpdv,
+            footprint: 0.0,
+        };
+
+        (geometry, self.material)
+    }
+
+    fn material(&self) -> &Material {
+        self.material
+    }
+
+    fn bounds(&self) -> (Vector3, Vector3) {
+        let center = self.pos.mult_point(&Vector3 { x: 0.0, y: 0.0, z: 0.0 });
+        let (x, y, z) = (self.pos.mult_dist(&Vector3 { x: 1.0, y: 0.0, z: 0.0 }),
+                         self.pos.mult_dist(&Vector3 { x: 0.0, y: 1.0, z: 0.0 }),
+                         self.pos.mult_dist(&Vector3 { x: 0.0, y: 0.0, z: 1.0 }));
+        let half = Vector3 {
+            x: 0.5 * (x.x * x.x + y.x * y.x + z.x * z.x).sqrt(),
+            y: 0.5 * (x.y * x.y + y.y * y.y + z.y * z.y).sqrt(),
+            z: 0.5 * (x.z * x.z + y.z * y.z + z.z * z.z).sqrt(),
+        };
+
+        (&center - &half, &center + &half)
+    }
+
+    // Exact unless the sphere is stretched
+    fn area(&self) -> f64 {
+        let axes = [Vector3 { x: 1.0, y: 0.0, z: 0.0 }, Vector3 { x: 0.0, y: 1.0, z: 0.0 }, Vector3 { x: 0.0, y: 0.0, z: 1.0 }];
+        PI * axes.iter().map(|n| self.stretch(n)).sum::<f64>() / 3.0
+    }
+
+    // Uniformly over the untransformed sphere, which has an area of PI
+    fn sample(&self, rng: &mut XorShiftRng) -> (Vector3, f64) {
+        let z = 1.0 - 2.0 * rng.gen_range::<f64>(0.0, 1.0);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen_range::<f64>(0.0, 1.0);
+        let n = Vector3 { x: r * phi.cos(), y: r * phi.sin(), z: z };
+
+        (self.pos.mult_point(&n.amplified(0.5)), 1.0 / (PI * self.stretch(&n)))
+    }
+
+    fn pdf(&self, point: &Vector3) -> f64 {
+        let n = self.pos.inverse().mult_point(point).unit();
+        1.0 / (PI * self.stretch(&n))
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Sphere").with(self.pos.digest()).with(self.material.digest()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sphere;
+    use material::Lambert;
+    use quad::Quad;
+    use surface::Surface;
+    use vector3::Vector3;
+    use direction::Direction;
+    use differential::Differential;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn sphere_coordinates() {
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let sphere = Sphere::new(&lambert);
+
+        // u grows from x towards z, the tangents follow u and v
+        let (g, _) = sphere.at(&Vector3 { x: 0.5, y: 0.0, z: 0.0 });
+        let (h, _) = sphere.at(&Vector3 { x: 0.3, y: 0.0, z: 0.4 });
+        assert!((g.u - 0.5).abs() < 1e-9 && h.u > g.u, "{} {}", g.u, h.u);
+        assert!(g.tangent.z > 0.99 && g.bitangent.y > 0.99, "{:?}", g);
+        assert!(g.shaded(&g.normal).bitangent.y > 0.99);
+    }
+
+    #[test]
+    fn sphere_footprint() {
+        // Around the equator u wraps the circumference and v half of it, so the
+        // footprint is 2 / PI of the one on a quad as wide as the sphere
+        let lambert = Lambert::new(1.0, 1.0, 1.0);
+        let sphere = Sphere::new(&lambert);
+        let quad = Quad::new(&lambert);
+        let zero = Direction { x: 0.0, y: 0.0, z: 0.0 };
+        let pixel = Differential {
+            dpdx: Vector3 { x: 0.004, y: 0.0, z: 0.0 },
+            dpdy: Vector3 { x: 0.0, y: 0.004, z: 0.0 },
+            dddx: zero.clone(),
+            dddy: zero,
+        };
 
-        (self.pos.mult_dir(&p.unit()), self.material)
+        let (g, _) = sphere.at(&Vector3 { x: 0.0, y: 0.0, z: -0.5 });
+        let (h, _) = quad.at(&Vector3 { x: 0.0, y: 0.0, z: 0.0 });
+        let (on_sphere, on_quad) = (pixel.footprint(&g), pixel.footprint(&h));
+        assert!((on_quad - 0.004).abs() < 1e-9, "{}", on_quad);
+        assert!((on_sphere - on_quad * 2.0 / PI).abs() < 1e-9, "{} {}", on_sphere, on_quad);
     }
 }
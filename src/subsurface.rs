@@ -0,0 +1,145 @@
+use energy::Energy;
+use encode::Digest;
+use direction::Direction;
+use material::{Material, black, white};
+use medium::Medium;
+use microfacet::fresnel_dielectric;
+use surface::Geometry;
+use rand::{Rng, XorShiftRng};
+
+// Translucent material for skin, wax and marble. The smooth dielectric surface
+// lets light into a scattering medium which the sampler walks through until
+// the light finds its way out again. Needs closed surfaces.
+#[derive(Debug)]
+pub struct Subsurface {
+    pub medium: Medium,
+    pub ior: f64,
+    albedo: Energy,
+}
+
+impl Subsurface {
+    // `albedo` is the color after many scattering events, `mean_free_path` the
+    // average distance light travels between them in scene units
+    pub fn new(albedo: Energy, mean_free_path: Energy) -> Subsurface {
+        Subsurface {
+            medium: Medium::subsurface(&albedo, &mean_free_path, 0.0),
+            ior: 1.4,
+            albedo: albedo,
+        }
+    }
+
+    pub fn marble() -> Subsurface {
+        Subsurface::new(Energy { x: 0.83, y: 0.79, z: 0.75 }, Energy { x: 0.09, y: 0.07, z: 0.05 })
+    }
+
+    pub fn skin() -> Subsurface {
+        Subsurface::new(Energy { x: 0.74, y: 0.53, z: 0.43 }, Energy { x: 0.12, y: 0.05, z: 0.03 })
+    }
+
+    pub fn wax() -> Subsurface {
+        Subsurface::new(Energy { x: 0.95, y: 0.9, z: 0.7 }, Energy { x: 0.15, y: 0.12, z: 0.06 })
+    }
+}
+
+impl Material for Subsurface {
+    fn sample(&self, g: &Geometry, inc: &Direction, _: f64, rng: &mut XorShiftRng) -> (bool, Direction, Energy, bool) {
+        // The side the light arrives from, and the relative index of the other side
+        let (norm, eta) = if inc.enters(&g.normal) {
+            (g.normal.clone(), self.ior)
+        } else {
+            (g.normal.invert(), 1.0 / self.ior)
+        };
+
+        let cos = -norm.cos(inc);
+        if rng.gen_range(0.0, 1.0) < fresnel_dielectric(cos, eta) {
+            return (true, inc.reflected(&norm), white(), true);
+        }
+
+        let (_, refracted) = inc.refracted(&norm, 1.0, eta);
+        (true, refracted, white(), true)
+    }
+
+    fn evaluate(&self, _: &Geometry, _: &Direction, _: &Direction) -> Energy {
+        black()
+    }
+
+    fn pdf(&self, _: &Geometry, _: &Direction, _: &Direction) -> f64 {
+        0.0
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+
+    fn albedo(&self, _: &Geometry) -> Energy {
+        self.albedo.clone()
+    }
+
+    fn digest(&self) -> u64 {
+        Digest::new("Subsurface").with(self.medium.digest()).value(self.ior).vector(&self.albedo).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Subsurface;
+    use medium::walk;
+    use scene::Scene;
+    use sphere::Sphere;
+    use surface::Surface;
+    use energy::Energy;
+    use ray3::Ray3;
+    use vector3::Vector3;
+    use direction::Direction;
+    use furnace;
+    use rand::{SeedableRng, XorShiftRng};
+
+    // Reference scene: light entering a sphere of the material, followed until it leaves
+    fn reflectance(m: &Subsurface) -> f64 {
+        let surfaces: Vec<Box<Surface>> = vec![Box::new(Sphere::new(m))];
+        let scene = Scene::new(&surfaces);
+        let mut rng = XorShiftRng::from_seed([2, 7, 1, 8]);
+
+        furnace::mean(5000, &mut rng, |rng| {
+            let mut ray = Ray3 {
+                origin: Vector3 { x: 0.0, y: 0.0, z: 3.0 },
+                direction: Direction { x: 0.0, y: 0.0, z: -1.0 },
+            };
+            let mut signal = Energy { x: 1.0, y: 1.0, z: 1.0 };
+
+            // Enters, walks and leaves, possibly after internal reflections
+            for _ in 0..32 {
+                let (surface, dist) = match scene.intersect(&ray) {
+                    Some(hit) => hit,
+                    None => break,
+                };
+                let point = ray.moved(dist);
+                let (geometry, mat) = surface.at(&point);
+                let (_, direction, strength, _) = mat.sample(&geometry, &ray.direction, dist, rng);
+                signal = &signal * &strength;
+                ray = Ray3 { origin: point, direction: direction };
+
+                if ray.direction.dot(&geometry.geometric) < 0.0 {
+                    let (reached, _) = walk(&scene, mat.medium().unwrap(), &mut ray, &mut signal, &mut Energy { x: 0.0, y: 0.0, z: 0.0 }, rng, &mut |_, _, _, _| {});
+                    if !reached {
+                        signal = Energy { x: 0.0, y: 0.0, z: 0.0 };
+                        break;
+                    }
+                }
+            }
+
+            signal
+        }).average()
+    }
+
+    #[test]
+    fn subsurface_conserves_energy() {
+        let white = Subsurface::new(Energy { x: 1.0, y: 1.0, z: 1.0 }, Energy { x: 0.05, y: 0.05, z: 0.05 });
+        let r = reflectance(&white);
+        assert!((r - 1.0).abs() < 0.05, "reflectance {}", r);
+
+        let gray = Subsurface::new(Energy { x: 0.5, y: 0.5, z: 0.5 }, Energy { x: 0.05, y: 0.05, z: 0.05 });
+        let r = reflectance(&gray);
+        assert!(r < 0.7 && r > 0.3, "reflectance {}", r);
+    }
+}
@@ -1,9 +1,120 @@
 use ray3::Ray3;
 use vector3::Vector3;
+use direction::Direction;
 use material::Material;
+use microfacet::Frame;
+use texture::Texture;
+use rand::XorShiftRng;
 use std::fmt::Debug;
 
+// Description of a surface at a point, `u` and `v` are texture coordinates.
+// Materials shade with `normal`, which normal and bump maps may tilt away from
+// the `geometric` normal of the surface. `tangent` and `bitangent` follow
+// increasing `u` and `v`.
+#[derive(Debug, Clone)]
+pub struct Geometry {
+    pub normal: Vector3,
+    pub geometric: Vector3,
+    pub tangent: Vector3,
+    pub bitangent: Vector3,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Vector3, // Change of the position along u
+    pub dpdv: Vector3,
+    pub footprint: f64, // Width of the area seen through a pixel in texture coordinates, set by the sampler
+}
+
+impl Geometry {
+    // Geometry with an arbitrary tangent frame, for surfaces without one
+    pub fn new(normal: &Vector3, u: f64, v: f64) -> Geometry {
+        let frame = Frame::new(normal);
+
+        Geometry {
+            normal: normal.clone(),
+            geometric: normal.clone(),
+            dpdu: frame.tangent.clone(),
+            dpdv: frame.bitangent.clone(),
+            tangent: frame.tangent,
+            bitangent: frame.bitangent,
+            u: u,
+            v: v,
+            footprint: 0.0,
+        }
+    }
+
+    // The same point shaded with another normal, the tangents are made orthogonal to it again
+    pub fn shaded(&self, normal: &Vector3) -> Geometry {
+        let tangent = (&self.tangent - &(normal * normal.dot(&self.tangent))).unit();
+
+        Geometry {
+            normal: normal.clone(),
+            geometric: self.geometric.clone(),
+            bitangent: &normal.cross(&tangent) * self.handedness(),
+            tangent: tangent,
+            u: self.u,
+            v: self.v,
+            dpdu: self.dpdu.clone(),
+            dpdv: self.dpdv.clone(),
+            footprint: self.footprint,
+        }
+    }
+
+    // Shading frame following the tangent of the surface. A tangent map turns it
+    // towards the direction its first two channels give, mapped from 0-1 to -1-1
+    // along the tangent and bitangent.
+    pub fn frame(&self, map: Option<&Texture>) -> Frame {
+        let (tangent, bitangent) = match map {
+            Some(map) => {
+                let t = map.filtered(self);
+                let (x, y) = (2.0 * t.x - 1.0, 2.0 * t.y - 1.0);
+                let tangent = (&(&self.tangent * x) + &(&self.bitangent * y)).unit();
+                (tangent.clone(), &self.normal.cross(&tangent) * self.handedness())
+            }
+            None => (self.tangent.clone(), self.bitangent.clone()),
+        };
+
+        Frame {
+            tangent: tangent,
+            bitangent: bitangent,
+            normal: self.normal.clone(),
+        }
+    }
+
+    // -1 when mirrored texture coordinates put the bitangent on the other side
+    // of the tangent than the normal would
+    fn handedness(&self) -> f64 {
+        if self.normal.cross(&self.tangent).dot(&self.bitangent) < 0.0 { -1.0 } else { 1.0 }
+    }
+
+    // Whether `dir` is on the same side of the shading and the geometric normal
+    pub fn consistent(&self, dir: &Vector3) -> bool {
+        dir.dot(&self.normal) * dir.dot(&self.geometric) > 0.0
+    }
+}
+
 pub trait Surface: Debug {
     fn intersect(&self, r: &Ray3) -> (bool, f64);
-    fn at(&self, v: &Vector3) -> (Vector3, &Material);
+    fn at(&self, v: &Vector3) -> (Geometry, &Material);
+    fn material(&self) -> &Material;
+
+    // Axis aligned box around the surface
+    fn bounds(&self) -> (Vector3, Vector3);
+
+    // Area in world space, for estimating the power of emissive surfaces
+    fn area(&self) -> f64;
+
+    // Picks a point on the surface for lighting from it. Returns the point and
+    // its density per unit area.
+    fn sample(&self, rng: &mut XorShiftRng) -> (Vector3, f64);
+
+    // Density per unit area of `sample` picking `point`
+    fn pdf(&self, point: &Vector3) -> f64;
+
+    // The normal of flat surfaces, which only emit towards one side
+    fn facing(&self) -> Option<Direction> {
+        None
+    }
+
+    // Hash of the placement and the material, for `Scene::hash`
+    fn digest(&self) -> u64;
 }
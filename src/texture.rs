@@ -0,0 +1,431 @@
+use energy::Energy;
+use surface::Geometry;
+use encode::{fnv_colors, Digest};
+use openexr;
+use image::{self, ImageError, ImageResult};
+use image::hdr::HDRDecoder;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// A material parameter evaluated at the surface coordinates returned by `Surface::at`.
+// Scalar parameters use the first channel.
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Constant(Energy),
+    Image(ImageTexture),
+    Checker {
+        scale: f64, // Number of squares along u and v
+        even: Energy,
+        odd: Energy,
+    },
+    Noise {
+        scale: f64,
+        octaves: usize, // Perlin fBm octaves
+        low: Energy,
+        high: Energy,
+    },
+    Voronoi {
+        scale: f64,
+        low: Energy, // Color at the cell centers
+        high: Energy, // Color towards the cell borders
+    },
+    Inverse(Box<Texture>), // One minus the texture, e.g. roughness from a gloss map
+}
+
+impl Texture {
+    pub fn constant(r: f64, g: f64, b: f64) -> Texture {
+        Texture::Constant(Energy { x: r, y: g, z: b })
+    }
+
+    pub fn value(n: f64) -> Texture {
+        Texture::Constant(Energy { x: n, y: n, z: n })
+    }
+
+    pub fn at(&self, u: f64, v: f64) -> Energy {
+        self.lookup(u, v, 0.0)
+    }
+
+    // Hash of the parameters, images only by the hash of their pixels
+    pub fn digest(&self) -> u64 {
+        match *self {
+            Texture::Constant(ref c) => Digest::new("Constant").vector(c),
+            Texture::Image(ref image) => Digest::new("Image").with(image.digest()),
+            Texture::Checker { scale, ref even, ref odd } => Digest::new("Checker").value(scale).vector(even).vector(odd),
+            Texture::Noise { scale, octaves, ref low, ref high } => {
+                Digest::new("Noise").values(&[scale, octaves as f64]).vector(low).vector(high)
+            }
+            Texture::Voronoi { scale, ref low, ref high } => Digest::new("Voronoi").value(scale).vector(low).vector(high),
+            Texture::Inverse(ref t) => Digest::new("Inverse").with(t.digest()),
+        }
+        .finish()
+    }
+
+    // Value averaged over the pixel footprint of the geometry
+    pub fn filtered(&self, g: &Geometry) -> Energy {
+        self.lookup(g.u, g.v, g.footprint)
+    }
+
+    pub fn filtered_scalar(&self, g: &Geometry) -> f64 {
+        self.filtered(g).x
+    }
+
+    // Value averaged over an area `width` wide in texture coordinates. Only images
+    // are prefiltered, procedural textures return the value at the center.
+    pub fn lookup(&self, u: f64, v: f64, width: f64) -> Energy {
+        match *self {
+            Texture::Constant(ref e) => e.clone(),
+            Texture::Image(ref image) => image.lookup(u, v, width),
+            Texture::Checker { scale, ref even, ref odd } => {
+                if ((u * scale).floor() + (v * scale).floor()) as i64 % 2 == 0 {
+                    even.clone()
+                } else {
+                    odd.clone()
+                }
+            }
+            Texture::Noise { scale, octaves, ref low, ref high } => low.lerp(high, 0.5 + 0.5 * fbm(u * scale, v * scale, octaves)),
+            Texture::Voronoi { scale, ref low, ref high } => low.lerp(high, voronoi(u * scale, v * scale).min(1.0)),
+            Texture::Inverse(ref t) => {
+                let e = t.lookup(u, v, width);
+                Energy {
+                    x: 1.0 - e.x,
+                    y: 1.0 - e.y,
+                    z: 1.0 - e.z,
+                }
+            }
+        }
+    }
+
+    pub fn scalar(&self, u: f64, v: f64) -> f64 {
+        self.at(u, v).x
+    }
+
+    // Offsets in u and v small enough to resolve the finest detail, used for slopes
+    pub fn step(&self) -> (f64, f64) {
+        match *self {
+            Texture::Image(ref image) => (1.0 / image.width as f64, 1.0 / image.height as f64),
+            Texture::Inverse(ref t) => t.step(),
+            _ => (1e-3, 1e-3),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+// Image with a pyramid of box filtered, halved copies for lookups over larger areas.
+// v runs from the bottom of the image to the top.
+#[derive(Clone)]
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pub wrap: Wrap,
+    levels: Vec<Level>,
+    digest: u64, // Of the pixels, taken once as they are large
+}
+
+#[derive(Clone)]
+struct Level {
+    width: usize,
+    height: usize,
+    pixels: Vec<Energy>,
+}
+
+impl ImageTexture {
+    // Radiance HDR and OpenEXR files keep their floating point values. Any other
+    // format supported by the image crate is read with 8 bits per channel, scaled to 0-1.
+    pub fn open(path: &Path, wrap: Wrap) -> ImageResult<ImageTexture> {
+        let extension = |name: &str| path.extension().is_some_and(|e| e.eq_ignore_ascii_case(name));
+        let (width, height, pixels) = if extension("exr") {
+            openexr::read(path)?
+        } else if extension("hdr") {
+            let decoder = HDRDecoder::new(BufReader::new(File::open(path).map_err(ImageError::IoError)?))?;
+            let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+            let pixels = decoder.read_image_hdr()?
+                .iter()
+                .map(|p| Energy { x: p[0] as f64, y: p[1] as f64, z: p[2] as f64 })
+                .collect();
+            (width as usize, height as usize, pixels)
+        } else {
+            let img = image::open(path)?.to_rgb();
+            let (width, height) = img.dimensions();
+            let pixels = img.pixels()
+                .map(|p| Energy { x: p[0] as f64 / 255.0, y: p[1] as f64 / 255.0, z: p[2] as f64 / 255.0 })
+                .collect();
+            (width as usize, height as usize, pixels)
+        };
+
+        if width == 0 || height == 0 {
+            return Err(ImageError::DimensionError);
+        }
+        Ok(ImageTexture::new(width, height, pixels, wrap))
+    }
+
+    // Pixels in rows from the top left, at least one
+    pub fn new(width: usize, height: usize, pixels: Vec<Energy>, wrap: Wrap) -> ImageTexture {
+        assert!(width > 0 && height > 0, "empty image");
+        assert_eq!(width * height, pixels.len());
+
+        let mut levels = vec![Level {
+                                  width: width,
+                                  height: height,
+                                  pixels: pixels,
+                              }];
+        while levels[levels.len() - 1].width > 1 || levels[levels.len() - 1].height > 1 {
+            let next = levels[levels.len() - 1].halved(wrap);
+            levels.push(next);
+        }
+
+        ImageTexture {
+            width: width,
+            height: height,
+            wrap: wrap,
+            digest: fnv_colors(levels[0].pixels.iter()),
+            levels: levels,
+        }
+    }
+
+    // Hash of the pixels and how they wrap
+    pub fn digest(&self) -> u64 {
+        Digest::new("ImageTexture").values(&[self.width as f64, self.height as f64, self.wrap as u8 as f64]).with(self.digest).finish()
+    }
+
+    pub fn at(&self, u: f64, v: f64) -> Energy {
+        self.levels[0].at(u, v, self.wrap)
+    }
+
+    // Trilinear interpolation between the two levels whose texels are closest to `width`
+    pub fn lookup(&self, u: f64, v: f64, width: f64) -> Energy {
+        let texels = width * self.width.max(self.height) as f64;
+        if texels <= 1.0 {
+            return self.at(u, v);
+        }
+
+        let level = texels.log2().min((self.levels.len() - 1) as f64);
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+
+        self.levels[lower].at(u, v, self.wrap).lerp(&self.levels[upper].at(u, v, self.wrap), level - lower as f64)
+    }
+}
+
+impl Level {
+    fn at(&self, u: f64, v: f64, mode: Wrap) -> Energy {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0, mode).lerp(self.texel(x0 + 1, y0, mode), tx);
+        let bottom = self.texel(x0, y0 + 1, mode).lerp(self.texel(x0 + 1, y0 + 1, mode), tx);
+        top.lerp(&bottom, ty)
+    }
+
+    fn texel(&self, x: i64, y: i64, mode: Wrap) -> &Energy {
+        let x = wrap(x, self.width, mode);
+        let y = wrap(y, self.height, mode);
+        &self.pixels[y * self.width + x]
+    }
+
+    // Averages blocks of 2x2 texels, odd sizes round up and share the last texel
+    fn halved(&self, mode: Wrap) -> Level {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let mut sum = Energy { x: 0.0, y: 0.0, z: 0.0 };
+                for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let (sx, sy) = ((2 * x + dx).min(self.width as i64 - 1), (2 * y + dy).min(self.height as i64 - 1));
+                    sum = &sum + self.texel(sx, sy, mode);
+                }
+                pixels.push(sum.amplified(0.25));
+            }
+        }
+
+        Level {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+}
+
+// The pixels only show up as their hash, as they are large
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "ImageTexture {{ width: {}, height: {}, wrap: {:?}, pixels: {:x} }}",
+               self.width,
+               self.height,
+               self.wrap,
+               self.digest)
+    }
+}
+
+fn wrap(i: i64, size: usize, mode: Wrap) -> usize {
+    let n = size as i64;
+    match mode {
+        Wrap::Repeat => (((i % n) + n) % n) as usize,
+        Wrap::Clamp => i.max(0).min(n - 1) as usize,
+        Wrap::Mirror => {
+            let m = ((i % (2 * n)) + 2 * n) % (2 * n);
+            (if m < n { m } else { 2 * n - 1 - m }) as usize
+        }
+    }
+}
+
+fn hash(x: i64, y: i64) -> u64 {
+    let mut h = (x as u64).wrapping_mul(0x9e3779b97f4a7c15) ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f);
+    h ^= h >> 29;
+    h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+    h ^ (h >> 32)
+}
+
+// Uniform number in 0-1 derived from a lattice point
+fn lattice(x: i64, y: i64, salt: u64) -> f64 {
+    (hash(x, y) ^ salt).wrapping_mul(0x94d049bb133111eb) as f64 / u64::MAX as f64
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+// Gradient noise in roughly -1 to 1
+pub fn perlin(x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i64, y0 as i64);
+
+    let corner = |cx: i64, cy: i64| {
+        let angle = lattice(ix + cx, iy + cy, 0) * 2.0 * PI;
+        angle.cos() * (fx - cx as f64) + angle.sin() * (fy - cy as f64)
+    };
+
+    let (u, v) = (fade(fx), fade(fy));
+    let a = corner(0, 0) + u * (corner(1, 0) - corner(0, 0));
+    let b = corner(0, 1) + u * (corner(1, 1) - corner(0, 1));
+    (a + v * (b - a)) * ::std::f64::consts::SQRT_2
+}
+
+pub fn fbm(x: f64, y: f64, octaves: usize) -> f64 {
+    let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 1.0, 1.0, 0.0);
+
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(x * frequency, y * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    (sum / total).clamp(-1.0, 1.0)
+}
+
+// Distance to the nearest feature point, one point per unit cell
+pub fn voronoi(x: f64, y: f64) -> f64 {
+    let (ix, iy) = (x.floor() as i64, y.floor() as i64);
+    let mut nearest = f64::INFINITY;
+
+    for cx in ix - 1..ix + 2 {
+        for cy in iy - 1..iy + 2 {
+            let px = cx as f64 + lattice(cx, cy, 1);
+            let py = cy as f64 + lattice(cx, cy, 2);
+            nearest = nearest.min(((px - x) * (px - x) + (py - y) * (py - y)).sqrt());
+        }
+    }
+
+    nearest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageTexture, Texture, Wrap, fbm, voronoi};
+    use energy::Energy;
+    use image::ImageError;
+    use exr::prelude::write_rgb_file;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    fn gray(n: f64) -> Energy {
+        Energy { x: n, y: n, z: n }
+    }
+
+    #[test]
+    fn texture_image_filtering() {
+        let image = ImageTexture::new(2, 1, vec![gray(0.0), gray(1.0)], Wrap::Clamp);
+
+        assert_eq!(gray(0.0), image.at(0.25, 0.5));
+        assert_eq!(gray(0.5), image.at(0.5, 0.5));
+        assert_eq!(gray(1.0), image.at(1.0, 0.5));
+
+        let repeat = ImageTexture::new(2, 1, vec![gray(0.0), gray(1.0)], Wrap::Repeat);
+        assert_eq!(gray(0.5), repeat.at(0.0, 0.5));
+    }
+
+    #[test]
+    fn texture_hdr_file() {
+        // Values above one survive, empty images are turned away
+        let path = env::temp_dir().join("texture_hdr_file.hdr");
+        File::create(&path).unwrap().write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n\x80\x80\x80\x83\x80\x80\x80\x7f").unwrap();
+        let image = ImageTexture::open(&path, Wrap::Clamp).unwrap();
+        assert_eq!(gray(4.0), image.at(0.0, 0.5));
+        assert_eq!(gray(0.25), image.at(1.0, 0.5));
+
+        File::create(&path).unwrap().write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 0 +X 0\n").unwrap();
+        let empty = ImageTexture::open(&path, Wrap::Clamp);
+        fs::remove_file(&path).unwrap();
+        match empty {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn texture_exr_file() {
+        let path = env::temp_dir().join("texture_exr_file.exr");
+        write_rgb_file(&path, 2, 1, |x, _| if x == 0 { (4.0f32, 4.0f32, 4.0f32) } else { (0.25, 0.25, 0.25) }).unwrap();
+        let image = ImageTexture::open(&path, Wrap::Clamp).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(gray(4.0), image.at(0.0, 0.5));
+        assert_eq!(gray(0.25), image.at(1.0, 0.5));
+    }
+
+    #[test]
+    fn texture_mipmap() {
+        // A fine checkerboard averages to gray once a lookup covers many texels
+        let pixels = (0..64).map(|i| gray(((i % 8 + i / 8) % 2) as f64)).collect();
+        let image = ImageTexture::new(8, 8, pixels, Wrap::Repeat);
+
+        assert_eq!(1.0, image.lookup(1.5 / 8.0, 1.0 - 0.5 / 8.0, 0.0).x);
+        assert!((image.lookup(0.3, 0.7, 1.0).x - 0.5).abs() < 1e-9);
+        assert!((image.lookup(0.3, 0.7, 0.5).x - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn texture_procedural() {
+        let checker = Texture::Checker {
+            scale: 2.0,
+            even: gray(0.0),
+            odd: gray(1.0),
+        };
+
+        assert_eq!(0.0, checker.scalar(0.25, 0.25));
+        assert_eq!(1.0, checker.scalar(0.75, 0.25));
+        assert_eq!(0.0, checker.scalar(0.75, 0.75));
+
+        for i in 0..100 {
+            let (x, y) = (i as f64 * 0.37, i as f64 * 0.11);
+            assert!(fbm(x, y, 4).abs() <= 1.0);
+            assert!(voronoi(x, y) < 1.5);
+        }
+    }
+}
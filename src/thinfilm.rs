@@ -0,0 +1,95 @@
+use energy::Energy;
+use encode::Digest;
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+// Wavelengths in nm averaged for each of the red, green and blue channels
+pub const BANDS: [[f64; 5]; 3] = [[600.0, 625.0, 650.0, 675.0, 700.0],
+                              [500.0, 525.0, 550.0, 575.0, 600.0],
+                              [400.0, 425.0, 450.0, 475.0, 500.0]];
+
+// A thin transparent film on top of a surface, as on soap bubbles and coated
+// lenses. Light reflected by the top and bottom of the film interferes, which
+// colors the reflection depending on the angle.
+#[derive(Debug, Clone)]
+pub struct ThinFilm {
+    pub thickness: f64, // In nm
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> ThinFilm {
+        ThinFilm {
+            thickness: thickness,
+            ior: ior,
+        }
+    }
+
+    pub fn digest(&self) -> u64 {
+        Digest::new("ThinFilm").values(&[self.thickness, self.ior]).finish()
+    }
+
+    // Reflectance at one wavelength in nm, seen from air at `cos` to the normal,
+    // over a substrate with complex index `eta` + i `k`
+    pub fn reflectance(&self, cos: f64, wavelength: f64, eta: f64, k: f64) -> f64 {
+        let n1 = Complex64::new(1.0, 0.0);
+        let n2 = Complex64::new(self.ior, 0.0);
+        let n3 = Complex64::new(eta, k);
+
+        // Snell's law with complex angles
+        let sin1 = Complex64::new((1.0 - cos * cos).max(0.0).sqrt(), 0.0);
+        let cos1 = Complex64::new(cos, 0.0);
+        let cos2 = (Complex64::new(1.0, 0.0) - (n1 * sin1 / n2) * (n1 * sin1 / n2)).sqrt();
+        let cos3 = (Complex64::new(1.0, 0.0) - (n1 * sin1 / n3) * (n1 * sin1 / n3)).sqrt();
+
+        let phase = n2 * cos2 * (4.0 * PI * self.thickness / wavelength);
+        let shift = (Complex64::new(0.0, 1.0) * phase).exp();
+
+        let airy = |r12: Complex64, r23: Complex64| {
+            let r = (r12 + r23 * shift) / (Complex64::new(1.0, 0.0) + r12 * r23 * shift);
+            r.norm_sqr()
+        };
+
+        let s = airy((n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2),
+                     (n2 * cos2 - n3 * cos3) / (n2 * cos2 + n3 * cos3));
+        let p = airy((n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2),
+                     (n3 * cos2 - n2 * cos3) / (n3 * cos2 + n2 * cos3));
+
+        ((s + p) / 2.0).clamp(0.0, 1.0)
+    }
+
+    // RGB approximation averaging a band of wavelengths per channel, with the
+    // substrate index given per channel
+    pub fn rgb(&self, cos: f64, eta: &Energy, k: &Energy) -> Energy {
+        let band = |i: usize, eta: f64, k: f64| BANDS[i].iter().map(|&w| self.reflectance(cos, w, eta, k)).sum::<f64>() / 5.0;
+
+        Energy {
+            x: band(0, eta.x, k.x),
+            y: band(1, eta.y, k.y),
+            z: band(2, eta.z, k.z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThinFilm;
+    use microfacet::{fresnel_dielectric, fresnel_conductor};
+
+    #[test]
+    fn thin_film_limits() {
+        // Without thickness only the substrate reflects
+        let none = ThinFilm::new(0.0, 1.33);
+        assert!((none.reflectance(0.8, 550.0, 1.5, 0.0) - fresnel_dielectric(0.8, 1.5)).abs() < 1e-9);
+        assert!((none.reflectance(0.8, 550.0, 0.2, 3.0) - fresnel_conductor(0.8, 0.2, 3.0)).abs() < 1e-9);
+
+        // A quarter wave coating of the geometric mean index cancels the reflection of glass
+        let coating = ThinFilm::new(550.0 / (4.0 * 1.5f64.sqrt()), 1.5f64.sqrt());
+        assert!(coating.reflectance(1.0, 550.0, 1.5, 0.0) < 1e-6);
+
+        // A soap film colors the reflection
+        let soap = ThinFilm::new(300.0, 1.33);
+        let rgb = soap.rgb(1.0, &::energy::Energy { x: 1.0, y: 1.0, z: 1.0 }, &::energy::Energy { x: 0.0, y: 0.0, z: 0.0 });
+        assert!((rgb.x - rgb.y).abs() > 0.01 || (rgb.y - rgb.z).abs() > 0.01);
+    }
+}
@@ -69,7 +69,7 @@ impl fmt::Debug for Vector3 {
     }
 }
 
-impl<'a, 'b> Add<&'b Vector3> for &'a Vector3 {
+impl<'b> Add<&'b Vector3> for &Vector3 {
     type Output = Vector3;
 
     fn add(self, rhs: &'b Vector3) -> Vector3 {
@@ -81,7 +81,7 @@ impl<'a, 'b> Add<&'b Vector3> for &'a Vector3 {
     }
 }
 
-impl<'a, 'b> Sub<&'b Vector3> for &'a Vector3 {
+impl<'b> Sub<&'b Vector3> for &Vector3 {
     type Output = Vector3;
 
     fn sub(self, rhs: &'b Vector3) -> Vector3 {
@@ -93,7 +93,7 @@ impl<'a, 'b> Sub<&'b Vector3> for &'a Vector3 {
     }
 }
 
-impl<'a, 'b> Mul<&'b Vector3> for &'a Vector3 {
+impl<'b> Mul<&'b Vector3> for &Vector3 {
     type Output = Vector3;
 
     fn mul(self, rhs: &'b Vector3) -> Vector3 {
@@ -105,7 +105,7 @@ impl<'a, 'b> Mul<&'b Vector3> for &'a Vector3 {
     }
 }
 
-impl<'a> Mul<f64> for &'a Vector3 {
+impl Mul<f64> for &Vector3 {
     type Output = Vector3;
 
     fn mul(self, rhs: f64) -> Vector3 {